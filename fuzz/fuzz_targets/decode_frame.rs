@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate chariot_slp;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = chariot_slp::decode_frame_from_slice(data);
+});
@@ -0,0 +1,119 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use error::*;
+use options::DecodeOptions;
+use slp::{SlpFile, SlpLogicalShape, SlpShapeHeader};
+
+use std::io::prelude::*;
+
+/// Hands out space in a texture atlas for [SlpFile::decode_into_atlas](struct.SlpFile.html#method.decode_into_atlas).
+pub trait AtlasAllocator {
+    /// Reserves a `width` by `height` rectangle for frame `frame_index`.
+    ///
+    /// Returns the destination, starting at the rectangle's top-left pixel, and the distance
+    /// in bytes between the starts of consecutive rows.
+    fn allocate(&mut self, frame_index: usize, width: u32, height: u32) -> (&mut [u8], u32);
+}
+
+impl SlpFile {
+    /// Decodes every frame in `cursor` straight into an atlas as RGBA, without keeping more
+    /// than one decoded frame around at a time.
+    ///
+    /// Frames are written with [blit_rgba](struct.SlpLogicalShape.html#method.blit_rgba).
+    /// Returns the frames' headers, in order, so their centers can be recorded alongside the
+    /// atlas rectangles.
+    pub fn decode_into_atlas<R, A>(cursor: &mut R,
+                                   palette: &[[u8; 3]; 256],
+                                   player_index: u8,
+                                   atlas: &mut A)
+                                   -> Result<Vec<SlpShapeHeader>>
+        where R: Read + Seek,
+              A: AtlasAllocator
+    {
+        let mut headers = Vec::new();
+        {
+            let write_frame = |frame_index: usize, shape: SlpLogicalShape| {
+                {
+                    let (width, height) = (shape.header.width, shape.header.height);
+                    let (dst, stride) = atlas.allocate(frame_index, width, height);
+                    try!(shape.blit_rgba(dst, stride as usize, palette, player_index));
+                }
+                headers.push(shape.header);
+                Ok(())
+            };
+            let options = DecodeOptions::default();
+            try!(SlpFile::decode_each_shape(cursor,
+                                            player_index,
+                                            &options,
+                                            None,
+                                            &mut None,
+                                            write_frame));
+        }
+        Ok(headers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtlasAllocator;
+    use slp::SlpFile;
+    use test_util::build_slp;
+    use std::io;
+
+    /// Stacks frames vertically in a fixed-width atlas.
+    struct ColumnAtlas {
+        pixels: Vec<u8>,
+        width: u32,
+        next_y: u32,
+    }
+
+    impl AtlasAllocator for ColumnAtlas {
+        fn allocate(&mut self, _frame_index: usize, _width: u32, height: u32) -> (&mut [u8], u32) {
+            let stride = self.width * 4;
+            let start = (self.next_y * stride) as usize;
+            self.next_y += height;
+            (&mut self.pixels[start..], stride)
+        }
+    }
+
+    #[test]
+    fn test_decode_into_atlas() {
+        let data = build_slp(&[(1, &[(0, 0, &[0x04, 1, 0x0F])]),
+                               (2, &[(0, 0, &[0x08, 2, 1, 0x0F])])]);
+        let mut palette = [[0u8; 3]; 256];
+        palette[1] = [10, 10, 10];
+        palette[2] = [20, 20, 20];
+
+        let mut atlas = ColumnAtlas {
+            pixels: vec![0u8; 2 * 2 * 4],
+            width: 2,
+            next_y: 0,
+        };
+        let headers = SlpFile::decode_into_atlas(&mut io::Cursor::new(data), &palette, 1, &mut atlas)
+            .unwrap();
+        assert_eq!(2, headers.len());
+        assert_eq!(vec![10, 10, 10, 255, 0, 0, 0, 0, 20, 20, 20, 255, 10, 10, 10, 255],
+                   atlas.pixels);
+    }
+}
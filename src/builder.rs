@@ -0,0 +1,129 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+use encode::IndexedFrame;
+use error::*;
+use slp::SlpFile;
+use std::io::{Seek, Write};
+
+/// Assembles an SLP out of frames of palette indices, taking care of the command streams and
+/// offset tables.
+///
+/// Frames are only encoded by [build](#method.build) or [write_to](#method.write_to).
+#[derive(Default)]
+pub struct SlpBuilder {
+    comment: String,
+    frames: Vec<IndexedFrame>,
+}
+
+impl SlpBuilder {
+    pub fn new() -> SlpBuilder {
+        SlpBuilder::default()
+    }
+
+    /// Sets the comment stored in the header, which can be at most 24 bytes.
+    pub fn set_comment(&mut self, comment: &str) {
+        self.comment = comment.to_string();
+    }
+
+    /// Adds a `width` by `height` frame of row-major palette indices, where
+    /// `transparent_index` marks the pixels that aren't drawn.
+    ///
+    /// `center` is the point that's placed where the frame is drawn.
+    pub fn add_frame(&mut self,
+                     width: u32,
+                     height: u32,
+                     pixels: &[u8],
+                     transparent_index: u8,
+                     center: (i32, i32)) {
+        self.frames.push(IndexedFrame {
+            width,
+            height,
+            indices: pixels.to_vec(),
+            transparent_index,
+            anchor: center,
+        });
+    }
+
+    /// Builds the file, failing if the comment is too long or a frame doesn't have
+    /// `width * height` pixels.
+    pub fn build(&self) -> Result<SlpFile> {
+        SlpFile::from_indexed_frames(&self.frames, &self.comment)
+    }
+
+    /// Builds the file and writes it to `out`, as by
+    /// [SlpFile::write_to](struct.SlpFile.html#method.write_to).
+    pub fn write_to<W: Write + Seek>(&self, out: &mut W) -> Result<()> {
+        let slp_file = try!(self.build());
+        slp_file.write_to(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SlpBuilder;
+    use slp::{DrawCommand, SlpFile};
+    use std::io;
+
+    #[test]
+    fn test_slp_builder_round_trip() {
+        let grid = [0, 0, 5, 5, 5, 0,
+                    7, 0, 0, 0, 0, 9,
+                    0, 0, 0, 0, 0, 0];
+        let mut builder = SlpBuilder::new();
+        builder.set_comment("built");
+        builder.add_frame(6, 3, &grid, 0, (3, 2));
+        builder.add_frame(1, 1, &[4], 0, (0, 0));
+
+        let mut out = io::Cursor::new(Vec::new());
+        builder.write_to(&mut out).unwrap();
+        out.set_position(0);
+        let slp_file = SlpFile::read_from(&mut out).unwrap();
+
+        assert_eq!("built", slp_file.header.comment_str());
+        assert_eq!(2, slp_file.shapes.len());
+        let shape = &slp_file.shapes[0];
+        assert_eq!((3, 2), shape.anchor());
+        assert_eq!(&grid[..], &shape.pixels[..]);
+        assert_eq!(vec![vec![DrawCommand::Skip { count: 2 },
+                             DrawCommand::Fill { count: 3 },
+                             DrawCommand::Skip { count: 1 }],
+                        vec![DrawCommand::Color { count: 1 },
+                             DrawCommand::Skip { count: 4 },
+                             DrawCommand::Color { count: 1 }],
+                        vec![DrawCommand::Skip { count: 6 }]],
+                   shape.commands);
+        assert!(shape.transparent_rows[2]);
+        assert_eq!(vec![4], slp_file.shapes[1].pixels);
+    }
+
+    #[test]
+    fn test_slp_builder_bad_frame() {
+        let mut builder = SlpBuilder::new();
+        builder.add_frame(2, 2, &[1, 2, 3], 0, (0, 0));
+        assert!(builder.build().is_err());
+
+        let mut builder = SlpBuilder::new();
+        builder.set_comment("this comment is longer than 24 bytes");
+        assert!(builder.build().is_err());
+    }
+}
@@ -0,0 +1,141 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use error::*;
+use options::DecodeOptions;
+use slp::{DecodeContext, RowStart, SlpFile, SlpLogicalShape, SlpShapeHeader, check_dimensions,
+          stream_len};
+use std::io::prelude::*;
+
+/// Decodes single frames of an SLP on demand, remembering where each of a frame's rows starts
+/// so decoding it again skips the outline and row offset tables.
+///
+/// This suits viewers that redraw the same few frames over and over.
+pub struct DecodedFrameCache {
+    headers: Vec<SlpShapeHeader>,
+    row_starts: Vec<Option<Vec<Option<RowStart>>>>,
+    options: DecodeOptions,
+}
+
+impl DecodedFrameCache {
+    /// Reads the file and shape headers in `cursor`, without decoding any frames.
+    pub fn new<R: Read + Seek>(cursor: &mut R) -> Result<DecodedFrameCache> {
+        let (_, headers) = try!(SlpFile::read_shape_headers(cursor));
+        let frame_count = headers.len();
+        Ok(DecodedFrameCache {
+            headers,
+            row_starts: vec![None; frame_count],
+            options: DecodeOptions::default(),
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Decodes frame `index` from `cursor`, which must hold the same file this was created
+    /// from.
+    ///
+    /// Frames decoded this way don't keep their raw encoding, so they're always re-encoded when
+    /// written.
+    pub fn decode_cached<R: Read + Seek>(&mut self,
+                                         cursor: &mut R,
+                                         index: usize,
+                                         player_index: u8)
+                                         -> Result<SlpLogicalShape> {
+        let header = match self.headers.get(index) {
+            Some(header) => header,
+            None => {
+                return Err(ErrorKind::InvalidSlp(format!("no frame {}; there are only {}",
+                                                         index,
+                                                         self.headers.len()))
+                    .into())
+            }
+        };
+        let mut shape = SlpLogicalShape::new();
+        shape.header = header.clone();
+        try!(check_dimensions(header, &self.options));
+        if header.is_zero_area() {
+            // As when reading the whole file, every row gets an empty row of commands
+            shape.commands = vec![Vec::new(); header.height as usize];
+            return Ok(shape);
+        }
+        let len = try!(stream_len(cursor));
+
+        if self.row_starts[index].is_none() {
+            let row_starts =
+                try!(SlpFile::read_row_starts(cursor, header, index, &self.options, len));
+            self.row_starts[index] = Some(row_starts);
+        }
+        let row_starts = self.row_starts[index].as_ref().unwrap();
+
+        let mut context = DecodeContext {
+            player_index,
+            options: &self.options,
+            len,
+            trace: &mut None,
+        };
+        try!(SlpFile::decode_rows(cursor, &mut shape, index, row_starts, &mut context));
+        Ok(shape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DecodedFrameCache;
+    use slp::SlpFile;
+    use std::io;
+    use test_util::build_slp;
+
+    #[test]
+    fn test_decoded_frame_cache() {
+        let data = build_slp(&[(1, &[(0, 0, &[0x04, 7, 0x0F])]),
+                               (2, &[(0x8000, 0x8000, &[]), (0, 0, &[0x08, 8, 9, 0x0F])])]);
+        let mut cursor = io::Cursor::new(data);
+        let mut cache = DecodedFrameCache::new(&mut cursor).unwrap();
+        assert_eq!(2, cache.frame_count());
+
+        for _ in 0..2 {
+            let shape = cache.decode_cached(&mut cursor, 1, 1u8).unwrap();
+            assert_eq!(vec![0, 0, 8, 9], shape.pixels);
+            assert!(shape.raw.is_none());
+        }
+        assert!(cache.row_starts[0].is_none());
+        assert!(cache.row_starts[1].is_some());
+        assert_eq!(vec![7], cache.decode_cached(&mut cursor, 0, 1u8).unwrap().pixels);
+        assert!(cache.decode_cached(&mut cursor, 2, 1u8).is_err());
+    }
+
+    #[test]
+    fn test_decoded_frame_cache_zero_area() {
+        let mut data = build_slp(&[(0, &[])]);
+        data[52] = 5;
+        let mut cursor = io::Cursor::new(data);
+        let slp_file = SlpFile::read_from(&mut cursor).unwrap();
+        cursor.set_position(0);
+        let mut cache = DecodedFrameCache::new(&mut cursor).unwrap();
+        let shape = cache.decode_cached(&mut cursor, 0, 1u8).unwrap();
+        assert_eq!(slp_file.shapes[0].commands, shape.commands);
+        shape.verify().unwrap();
+    }
+}
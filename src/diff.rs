@@ -0,0 +1,185 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use slp::{SlpFile, SlpLogicalShape};
+
+use std::fmt;
+
+/// A single way in which two [SlpFile](struct.SlpFile.html)s differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Version([u8; 4], [u8; 4]),
+    Comment([u8; 24], [u8; 24]),
+    ShapeCount(usize, usize),
+
+    /// The shape's `(width, height)` differs.
+    Dimensions { shape: usize, left: (u32, u32), right: (u32, u32) },
+
+    /// The shape's `(center_x, center_y)` differs.
+    Center { shape: usize, left: (i32, i32), right: (i32, i32) },
+
+    /// Pixels or draw commands differ in `count` places, the first of which is at `first`.
+    Pixels { shape: usize, count: usize, first: (u32, u32) },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Difference::Version(ref left, ref right) => {
+                write!(f,
+                       "version: {} vs {}",
+                       String::from_utf8_lossy(left),
+                       String::from_utf8_lossy(right))
+            }
+            Difference::Comment(..) => write!(f, "comments differ"),
+            Difference::ShapeCount(left, right) => write!(f, "shape count: {} vs {}", left, right),
+            Difference::Dimensions { shape, left, right } => {
+                write!(f,
+                       "shape {}: size {}x{} vs {}x{}",
+                       shape,
+                       left.0,
+                       left.1,
+                       right.0,
+                       right.1)
+            }
+            Difference::Center { shape, left, right } => {
+                write!(f,
+                       "shape {}: center ({}, {}) vs ({}, {})",
+                       shape,
+                       left.0,
+                       left.1,
+                       right.0,
+                       right.1)
+            }
+            Difference::Pixels { shape, count, first } => {
+                write!(f,
+                       "shape {}: {} pixels differ, starting at ({}, {})",
+                       shape,
+                       count,
+                       first.0,
+                       first.1)
+            }
+        }
+    }
+}
+
+impl SlpFile {
+    /// Compares two files by their decoded content rather than their bytes.
+    ///
+    /// Shapes are compared pairwise up to the shorter file's shape count. Pixels are only
+    /// compared for shapes with the same dimensions. An empty result means the files decode
+    /// to the same thing.
+    pub fn structural_diff(&self, other: &SlpFile) -> Vec<Difference> {
+        let mut differences = Vec::new();
+        if self.header.file_version != other.header.file_version {
+            differences.push(Difference::Version(self.header.file_version,
+                                                 other.header.file_version));
+        }
+        if self.header.comment != other.header.comment {
+            differences.push(Difference::Comment(self.header.comment, other.header.comment));
+        }
+        if self.shapes.len() != other.shapes.len() {
+            differences.push(Difference::ShapeCount(self.shapes.len(), other.shapes.len()));
+        }
+
+        for (index, (left, right)) in self.shapes.iter().zip(other.shapes.iter()).enumerate() {
+            diff_shapes(index, left, right, &mut differences);
+        }
+        differences
+    }
+}
+
+fn diff_shapes(index: usize,
+               left: &SlpLogicalShape,
+               right: &SlpLogicalShape,
+               differences: &mut Vec<Difference>) {
+    let left_size = (left.header.width, left.header.height);
+    let right_size = (right.header.width, right.header.height);
+    let left_center = (left.header.center_x, left.header.center_y);
+    let right_center = (right.header.center_x, right.header.center_y);
+
+    if left_center != right_center {
+        differences.push(Difference::Center {
+            shape: index,
+            left: left_center,
+            right: right_center,
+        });
+    }
+    if left_size != right_size {
+        differences.push(Difference::Dimensions {
+            shape: index,
+            left: left_size,
+            right: right_size,
+        });
+        return;
+    }
+
+    let mut count = 0;
+    let mut first = (0, 0);
+    let pixels = left.enumerate_pixels().zip(right.enumerate_pixels());
+    for ((x, y, command, value), (_, _, other_command, other_value)) in pixels {
+        if command != other_command || value != other_value {
+            if count == 0 {
+                first = (x, y);
+            }
+            count += 1;
+        }
+    }
+    if count > 0 {
+        differences.push(Difference::Pixels {
+            shape: index,
+            count,
+            first,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Difference;
+    use slp::SlpFile;
+    use test_util::build_slp;
+    use std::io;
+
+    #[test]
+    fn test_structural_diff() {
+        let data = build_slp(&[(2, &[(0, 0, &[0x08, 1, 2, 0x0F])]),
+                               (1, &[(0, 0, &[0x04, 3, 0x0F])])]);
+        let original = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        let mut edited = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        assert!(original.structural_diff(&edited).is_empty());
+
+        edited.shapes[0].pixels[1] = 9;
+        edited.shapes[1].header.center_x = 4;
+        edited.shapes[1].header.width = 2;
+        let differences = original.structural_diff(&edited);
+        assert_eq!(vec![Difference::Pixels { shape: 0, count: 1, first: (1, 0) },
+                        Difference::Center { shape: 1, left: (0, 0), right: (4, 0) },
+                        Difference::Dimensions { shape: 1, left: (1, 1), right: (2, 1) }],
+                   differences);
+        assert_eq!("shape 0: 1 pixels differ, starting at (1, 0)", differences[0].to_string());
+
+        edited.shapes.pop();
+        assert_eq!(Difference::ShapeCount(2, 1), original.structural_diff(&edited)[0]);
+    }
+}
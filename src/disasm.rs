@@ -0,0 +1,176 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use chariot_io_tools::ReadExt;
+use error::*;
+use slp::{SlpFile, seek_checked, stream_len};
+use std::io::prelude::*;
+
+impl SlpFile {
+    /// Lists the commands in each row of shape `index`, such as
+    /// `row 0: copy 5, skip 3, fill 10 color 0x42, eol`.
+    ///
+    /// `cursor` must hold the file this was read from; the commands are read from it again
+    /// rather than being reconstructed from the decoded pixels.
+    pub fn disassemble_frame<R: Read + Seek>(&self,
+                                             cursor: &mut R,
+                                             index: usize)
+                                             -> Result<String> {
+        let header = match self.shapes.get(index) {
+            Some(shape) => &shape.header,
+            None => {
+                return Err(ErrorKind::InvalidSlp(format!("no shape {}; there are only {}",
+                                                         index,
+                                                         self.shapes.len()))
+                    .into())
+            }
+        };
+        let len = try!(stream_len(cursor));
+
+        let mut listing = String::new();
+        for y in 0..header.height {
+            try!(seek_checked(cursor, header.shape_outline_offset as u64 + y as u64 * 4, len));
+            let left = try!(cursor.read_u16());
+            let right = try!(cursor.read_u16());
+            match (left == 0x8000, right == 0x8000) {
+                (true, true) => {
+                    listing.push_str(&format!("row {}: transparent\n", y));
+                    continue;
+                }
+                (false, false) => {}
+                _ => {
+                    listing.push_str(&format!("row {}: malformed outline ({:#06x}, {:#06x})\n",
+                                              y,
+                                              left,
+                                              right));
+                    continue;
+                }
+            }
+
+            try!(seek_checked(cursor, header.shape_data_offsets as u64 + y as u64 * 4, len));
+            let data_offset = try!(cursor.read_u32());
+            try!(seek_checked(cursor, data_offset as u64, len));
+            let commands = try!(disassemble_row(cursor, y));
+            listing.push_str(&format!("row {}: {}\n", y, commands.join(", ")));
+        }
+        Ok(listing)
+    }
+}
+
+/// Reads commands up to and including the end of line, describing each one.
+fn disassemble_row<R: Read>(cursor: &mut R, y: u32) -> Result<Vec<String>> {
+    use slp::SlpEncodedLength::*;
+
+    let mut commands = Vec::new();
+    let mut x = 0;
+    loop {
+        let cmd_byte = try!(cursor.read_u8());
+        let (command, length) = match cmd_byte & 0b00001111 {
+            0b0000 | 0b0100 | 0b1000 | 0b1100 => {
+                let length = try!(SixUpperBit.decode(cmd_byte, x, y, cursor));
+                try!(skip_bytes(cursor, length));
+                (format!("copy {}", length), length)
+            }
+            0b0001 | 0b0101 | 0b1001 | 0b1101 => {
+                let length = try!(SixUpperBit.decode(cmd_byte, x, y, cursor));
+                (format!("skip {}", length), length)
+            }
+            0b0010 => {
+                let length = try!(LargeLength.decode(cmd_byte, x, y, cursor));
+                try!(skip_bytes(cursor, length));
+                (format!("copy {}", length), length)
+            }
+            0b0011 => {
+                let length = try!(LargeLength.decode(cmd_byte, x, y, cursor));
+                (format!("skip {}", length), length)
+            }
+            0b0110 => {
+                let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                try!(skip_bytes(cursor, length));
+                (format!("colorize {}", length), length)
+            }
+            0b0111 => {
+                let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                let color = try!(cursor.read_u8());
+                (format!("fill {} color {:#04x}", length, color), length)
+            }
+            0b1010 => {
+                let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                let color = try!(cursor.read_u8());
+                (format!("transform {} color {:#04x}", length, color), length)
+            }
+            0b1011 => {
+                let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                (format!("shadow {}", length), length)
+            }
+            0b1110 => {
+                match cmd_byte >> 4 {
+                    0x5 | 0x7 => {
+                        let length = try!(cursor.read_u8()) as usize;
+                        (format!("extended {:#04x} {}", cmd_byte, length), length)
+                    }
+                    0x4 | 0x6 => (format!("extended {:#04x}", cmd_byte), 1),
+                    _ => (format!("extended {:#04x}", cmd_byte), 0),
+                }
+            }
+            _ => {
+                commands.push("eol".to_string());
+                return Ok(commands);
+            }
+        };
+        commands.push(command);
+        x += length as u32;
+    }
+}
+
+fn skip_bytes<R: Read>(cursor: &mut R, count: usize) -> Result<()> {
+    let mut skipped = Vec::with_capacity(count);
+    try!(cursor.by_ref().take(count as u64).read_to_end(&mut skipped));
+    if skipped.len() < count {
+        return Err(ErrorKind::InvalidSlp("command runs past the end of the file".into()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use slp::SlpFile;
+    use std::io;
+    use test_util::single_shape_slp;
+
+    #[test]
+    fn test_slp_file_disassemble_frame() {
+        let data = single_shape_slp(18,
+                                    &[(0, 0, &[0x14, 1, 2, 3, 4, 5, 0x0D, 0xA7, 0x42, 0x0F]),
+                                      (0x8000, 0x8000, &[]),
+                                      (2, 6, &[0x26, 1, 2, 0x4E, 0x2B, 0x03, 0x05, 0x0F])]);
+        let mut cursor = io::Cursor::new(data);
+        let slp_file = SlpFile::read_from(&mut cursor).unwrap();
+        let listing = slp_file.disassemble_frame(&mut cursor, 0).unwrap();
+        assert_eq!("row 0: copy 5, skip 3, fill 10 color 0x42, eol\n\
+                    row 1: transparent\n\
+                    row 2: colorize 2, extended 0x4e, shadow 2, skip 5, eol\n",
+                   listing);
+        assert!(slp_file.disassemble_frame(&mut cursor, 1).is_err());
+    }
+}
@@ -0,0 +1,886 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use error::*;
+use options::{OptimizeLevel, WriteOptions};
+use slp::{PixelKind, RawCommand, SlpFile, SlpHeader, SlpLogicalShape, SlpShapeHeader,
+          row_commands};
+use std::cmp;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::Path;
+
+/// Size of an encoded [SlpHeader](struct.SlpHeader.html) in bytes.
+pub const SLP_HEADER_SIZE: u64 = 32;
+
+/// Size of an encoded [SlpShapeHeader](struct.SlpShapeHeader.html) in bytes.
+pub const SHAPE_HEADER_SIZE: u64 = 32;
+
+/// Outline value marking a row with nothing drawn in it.
+const TRANSPARENT_ROW: usize = 0x8000;
+
+const END_OF_LINE: u8 = 0x0F;
+
+/// Identical pixels in a row before a fill is cheaper than a copy.
+const MIN_FILL_LENGTH: usize = 3;
+
+/// A frame of already-indexed art, for building an SLP with
+/// [SlpFile::from_indexed_frames](struct.SlpFile.html#method.from_indexed_frames).
+#[derive(Debug, Clone)]
+pub struct IndexedFrame {
+    pub width: u32,
+    pub height: u32,
+
+    /// Palette indices in row-major order, `width * height` of them.
+    pub indices: Vec<u8>,
+
+    /// The index that marks a pixel as transparent.
+    pub transparent_index: u8,
+
+    /// The frame's `(center_x, center_y)`.
+    pub anchor: (i32, i32),
+}
+
+/// How runs of one kind of pixel value are copied and filled.
+struct ValueCommands {
+    fill_opcode: u8,
+    copy: fn(&mut Vec<u8>, &[u8]),
+    copy_size: fn(usize) -> usize,
+}
+
+const COLOR_COMMANDS: ValueCommands = ValueCommands {
+    fill_opcode: 0x07,
+    copy: encode_copy,
+    copy_size,
+};
+
+const REMAP_COMMANDS: ValueCommands = ValueCommands {
+    fill_opcode: 0x0A,
+    copy: encode_remap_copy,
+    copy_size: remap_copy_size,
+};
+
+/// One row of a shape, as it's stored in the outline table and command stream.
+pub struct EncodedRow {
+    /// Transparent pixels before the first command, or `0x8000` if the row is transparent.
+    pub left: u16,
+
+    /// Transparent pixels after the last command, or `0x8000` if the row is transparent.
+    pub right: u16,
+
+    pub commands: Vec<u8>,
+}
+
+/// Encodes every row of `shape` into drawing commands.
+pub fn encode_shape(shape: &SlpLogicalShape, options: &WriteOptions) -> Result<Vec<EncodedRow>> {
+    try!(shape.verify());
+    // Player colors come out of the runs as their exact relative index, whatever the pixels
+    // were resolved to
+    let pixels: Vec<(PixelKind, u8)> = shape.drawn_pixels().collect();
+    let width = shape.header.width as usize;
+    (0..shape.header.height)
+        .map(|y| {
+            let start = y as usize * width;
+            encode_row(shape, y, &pixels[start..start + width], options.optimize)
+        })
+        .collect()
+}
+
+/// Lays out `shape`'s outline table, row offsets and commands as they'll be stored starting
+/// `offset` bytes into a file, returning them along with a shape header that points at them.
+///
+/// Shapes that haven't been modified since they were read keep their original encoding, moved
+/// to `offset`.
+pub fn encode_shape_at(shape: &SlpLogicalShape,
+                       offset: u32,
+                       options: &WriteOptions)
+                       -> Result<(SlpShapeHeader, Vec<u8>)> {
+    let mut header = shape.header.clone();
+    let height = shape.header.height;
+
+    if let Some(raw) = shape.unmodified_raw() {
+        try!(file_offset(offset as u64 + raw.data.len() as u64));
+        // Both tables lie within the raw data, and only the row offsets point elsewhere in it
+        let mut data = raw.data.clone();
+        let table_start = (shape.header.shape_data_offsets - raw.offset) as usize;
+        for y in 0..height as usize {
+            let entry = &mut data[table_start + y * 4..table_start + y * 4 + 4];
+            let row_offset = read_u32(entry).wrapping_sub(raw.offset).wrapping_add(offset);
+            entry.copy_from_slice(&u32_bytes(row_offset));
+        }
+        header.shape_outline_offset = shape.header.shape_outline_offset - raw.offset + offset;
+        header.shape_data_offsets = shape.header.shape_data_offsets - raw.offset + offset;
+        return Ok((header, data));
+    }
+
+    let rows = try!(encode_shape(shape, options));
+    let size = height as u64 * 8 + rows.iter().map(|row| row.commands.len() as u64).sum::<u64>();
+    try!(file_offset(offset as u64 + size));
+    header.shape_outline_offset = offset;
+    header.shape_data_offsets = offset + height * 4;
+
+    let mut data = Vec::new();
+    for row in &rows {
+        data.extend_from_slice(&u16_bytes(row.left));
+        data.extend_from_slice(&u16_bytes(row.right));
+    }
+    let mut row_offset = offset + height * 8;
+    for row in &rows {
+        data.extend_from_slice(&u32_bytes(row_offset));
+        row_offset += row.commands.len() as u32;
+    }
+    for row in &rows {
+        data.extend_from_slice(&row.commands);
+    }
+    Ok((header, data))
+}
+
+/// Returns the number of bytes `shape`'s outline table, row offsets and commands take up.
+///
+/// Shapes that haven't been modified since they were read keep their original encoding.
+pub fn encoded_shape_size(shape: &SlpLogicalShape, options: &WriteOptions) -> Result<u64> {
+    if let Some(raw) = shape.unmodified_raw() {
+        return Ok(raw.data.len() as u64);
+    }
+    let rows = try!(encode_shape(shape, options));
+    let tables = rows.len() as u64 * 8;
+    Ok(rows.iter().fold(tables, |size, row| size + row.commands.len() as u64))
+}
+
+/// Encodes row `y` of `shape`, whose pixels are given as their kind and index.
+fn encode_row(shape: &SlpLogicalShape,
+              y: u32,
+              pixels: &[(PixelKind, u8)],
+              level: OptimizeLevel)
+              -> Result<EncodedRow> {
+    let width = shape.header.width as usize;
+    let (kinds, values): (Vec<PixelKind>, Vec<u8>) = pixels.iter().cloned().unzip();
+
+    let raw_commands: Vec<&RawCommand> =
+        shape.raw_commands.iter().filter(|command| command.y == y).collect();
+
+    // Extended commands count as drawn, even if they don't cover any pixels
+    let drawn_left = kinds.iter().position(|&kind| kind != PixelKind::Skip);
+    let raw_left = raw_commands.iter().map(|command| command.x as usize).min();
+    let left = match (drawn_left, raw_left) {
+        (Some(drawn), Some(raw)) => cmp::min(drawn, raw),
+        (Some(left), None) | (None, Some(left)) => left,
+        (None, None) => {
+            return Ok(EncodedRow {
+                left: TRANSPARENT_ROW as u16,
+                right: TRANSPARENT_ROW as u16,
+                commands: vec![END_OF_LINE],
+            })
+        }
+    };
+    let drawn_right = kinds.iter().rposition(|&kind| kind != PixelKind::Skip).map_or(0, |x| x + 1);
+    let raw_right = raw_commands.iter()
+        .map(|command| (command.x + command.length) as usize)
+        .max()
+        .unwrap_or(0);
+    let right = cmp::max(drawn_right, raw_right);
+    if left >= TRANSPARENT_ROW || width - right >= TRANSPARENT_ROW {
+        return Err(ErrorKind::InvalidSlp(format!("row {} is too wide to encode", y)).into());
+    }
+
+    let mut row = Vec::new();
+
+    let mut next_raw = 0;
+    let mut x = left;
+    while x < right || next_raw < raw_commands.len() {
+        if let Some(raw) = raw_commands.get(next_raw).filter(|raw| raw.x as usize <= x) {
+            row.extend_from_slice(&raw.bytes);
+            x = cmp::max(x, (raw.x + raw.length) as usize);
+            next_raw += 1;
+            continue;
+        }
+
+        let run_limit = raw_commands.get(next_raw).map_or(right, |raw| raw.x as usize);
+        let kind = kinds[x];
+        let run_end = (x..run_limit).find(|&i| kinds[i] != kind).unwrap_or(run_limit);
+        let run = &values[x..run_end];
+        match kind {
+            PixelKind::Skip => encode_skip(&mut row, run.len()),
+            PixelKind::Color => encode_values(&mut row, run, &COLOR_COMMANDS, level),
+            PixelKind::Remap => encode_values(&mut row, run, &REMAP_COMMANDS, level),
+            PixelKind::Shadow => encode_shadow(&mut row, run.len()),
+            PixelKind::Outline => encode_outline(&mut row, 0x4E, run.len()),
+            PixelKind::BlackOutline => encode_outline(&mut row, 0x6E, run.len()),
+        }
+        x = run_end;
+    }
+    row.push(END_OF_LINE);
+    Ok(EncodedRow {
+        left: left as u16,
+        right: (width - right) as u16,
+        commands: row,
+    })
+}
+
+fn u16_bytes(value: u16) -> [u8; 2] {
+    [value as u8, (value >> 8) as u8]
+}
+
+/// Returns `offset` as the `u32` offsets into a file are stored as, failing with `InvalidSlp`
+/// if it's past the 4 GiB they can reach.
+pub fn file_offset(offset: u64) -> Result<u32> {
+    u32::try_from(offset).map_err(|_| {
+        ErrorKind::InvalidSlp(format!("offset {} is past the 4 GiB an SLP can address", offset))
+            .into()
+    })
+}
+
+pub fn u32_bytes(value: u32) -> [u8; 4] {
+    [value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0] as u32 | (bytes[1] as u32) << 8 | (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24
+}
+
+/// Returns the length of the run of identical values at the start of `values`.
+fn identical_run(values: &[u8]) -> usize {
+    values.iter().position(|&v| v != values[0]).unwrap_or(values.len())
+}
+
+fn encode_skip(out: &mut Vec<u8>, mut length: usize) {
+    while length > 0 {
+        let chunk = cmp::min(length, 0xFFF);
+        if chunk < 64 {
+            out.push((chunk << 2) as u8 | 0x01);
+        } else {
+            out.push(((chunk >> 8) << 4) as u8 | 0x03);
+            out.push(chunk as u8);
+        }
+        length -= chunk;
+    }
+}
+
+/// Emits block copies (`0x00`) and large block copies (`0x02`) of `pixels`.
+fn encode_copy(out: &mut Vec<u8>, pixels: &[u8]) {
+    for chunk in pixels.chunks(0xFFF) {
+        if chunk.len() < 64 {
+            out.push((chunk.len() << 2) as u8);
+        } else {
+            out.push(((chunk.len() >> 8) << 4) as u8 | 0x02);
+            out.push(chunk.len() as u8);
+        }
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Returns how many bytes [encode_copy](fn.encode_copy.html) emits for `length` pixels.
+fn copy_size(length: usize) -> usize {
+    let full_chunks = length / 0xFFF;
+    let rest = length % 0xFFF;
+    let rest_header = match rest {
+        0 => 0,
+        1..=63 => 1,
+        _ => 2,
+    };
+    length + full_chunks * 2 + rest_header
+}
+
+/// Emits copy and colorize blocks (`0x06`) of player color indices.
+fn encode_remap_copy(out: &mut Vec<u8>, relative_indices: &[u8]) {
+    for chunk in relative_indices.chunks(0xFF) {
+        encode_short(out, 0x06, chunk.len());
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Returns how many bytes [encode_remap_copy](fn.encode_remap_copy.html) emits for `length`
+/// indices.
+fn remap_copy_size(length: usize) -> usize {
+    length + short_chunks_size(length)
+}
+
+/// Returns how many bytes the command headers of `length` split into runs of at most 255 take.
+fn short_chunks_size(length: usize) -> usize {
+    let rest_header = match length % 0xFF {
+        0 => 0,
+        1..=15 => 1,
+        _ => 2,
+    };
+    length / 0xFF * 2 + rest_header
+}
+
+/// Returns how many bytes filling `length` pixels with one value takes.
+fn fill_size(length: usize) -> usize {
+    short_chunks_size(length) + (length + 0xFE) / 0xFF
+}
+
+/// Emits a single command whose length is stored in the upper 4 bits, or in the next byte if
+/// it doesn't fit. `length` must be at most 255.
+fn encode_short(out: &mut Vec<u8>, opcode: u8, length: usize) {
+    if length < 16 {
+        out.push((length << 4) as u8 | opcode);
+    } else {
+        out.push(opcode);
+        out.push(length as u8);
+    }
+}
+
+fn encode_shadow(out: &mut Vec<u8>, mut length: usize) {
+    while length > 0 {
+        let chunk = cmp::min(length, 0xFF);
+        encode_short(out, 0x0B, chunk);
+        length -= chunk;
+    }
+}
+
+/// Emits extended outline commands, where `opcode` draws a single pixel and the opcode after
+/// it draws a span.
+fn encode_outline(out: &mut Vec<u8>, opcode: u8, mut length: usize) {
+    while length > 0 {
+        let chunk = cmp::min(length, 0xFF);
+        if chunk == 1 {
+            out.push(opcode);
+        } else {
+            out.push(opcode + 0x10);
+            out.push(chunk as u8);
+        }
+        length -= chunk;
+    }
+}
+
+fn encode_fill(out: &mut Vec<u8>, opcode: u8, value: u8, length: usize) {
+    let mut remaining = length;
+    while remaining > 0 {
+        let chunk = cmp::min(remaining, 0xFF);
+        encode_short(out, opcode, chunk);
+        out.push(value);
+        remaining -= chunk;
+    }
+}
+
+/// Encodes `values` as a mix of fills and copies, chosen according to `level`.
+fn encode_values(out: &mut Vec<u8>,
+                 values: &[u8],
+                 commands: &ValueCommands,
+                 level: OptimizeLevel) {
+    match level {
+        OptimizeLevel::None => (commands.copy)(out, values),
+        OptimizeLevel::Normal => encode_values_greedy(out, values, commands),
+        OptimizeLevel::Max => encode_values_smallest(out, values, commands),
+    }
+}
+
+/// Fills every run of at least `MIN_FILL_LENGTH` identical values and copies the rest.
+fn encode_values_greedy(out: &mut Vec<u8>, values: &[u8], commands: &ValueCommands) {
+    let mut copy_start = 0;
+    let mut x = 0;
+    while x < values.len() {
+        let fill_length = identical_run(&values[x..]);
+        if fill_length < MIN_FILL_LENGTH {
+            x += fill_length;
+            continue;
+        }
+        (commands.copy)(out, &values[copy_start..x]);
+        encode_fill(out, commands.fill_opcode, values[x], fill_length);
+        x += fill_length;
+        copy_start = x;
+    }
+    (commands.copy)(out, &values[copy_start..]);
+}
+
+/// Encodes `values` in as few bytes as possible, treating each run of identical values as
+/// either one fill or part of a copy.
+fn encode_values_smallest(out: &mut Vec<u8>, values: &[u8], commands: &ValueCommands) {
+    let mut run_starts = Vec::new();
+    let mut x = 0;
+    while x < values.len() {
+        run_starts.push(x);
+        x += identical_run(&values[x..]);
+    }
+    run_starts.push(values.len());
+
+    // smallest[k] is the fewest bytes the first k runs can be encoded in, reached from
+    // `from[k]` with a fill of run k - 1 if `filled[k]`, or a copy of runs from[k]..k otherwise
+    let run_count = run_starts.len() - 1;
+    let mut smallest = vec![0usize; run_count + 1];
+    let mut from = vec![0usize; run_count + 1];
+    let mut filled = vec![false; run_count + 1];
+    for k in 1..run_count + 1 {
+        smallest[k] = smallest[k - 1] + fill_size(run_starts[k] - run_starts[k - 1]);
+        from[k] = k - 1;
+        filled[k] = true;
+        for j in 0..k {
+            let size = smallest[j] + (commands.copy_size)(run_starts[k] - run_starts[j]);
+            if size < smallest[k] {
+                smallest[k] = size;
+                from[k] = j;
+                filled[k] = false;
+            }
+        }
+    }
+
+    let mut segments = Vec::new();
+    let mut k = run_count;
+    while k > 0 {
+        segments.push((from[k], k, filled[k]));
+        k = from[k];
+    }
+    for &(j, k, fill) in segments.iter().rev() {
+        let (start, end) = (run_starts[j], run_starts[k]);
+        if fill {
+            encode_fill(out, commands.fill_opcode, values[start], end - start);
+        } else {
+            (commands.copy)(out, &values[start..end]);
+        }
+    }
+}
+
+impl SlpHeader {
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> Result<()> {
+        let mut bytes = Vec::with_capacity(SLP_HEADER_SIZE as usize);
+        bytes.extend_from_slice(&self.file_version);
+        bytes.extend_from_slice(&u32_bytes(self.shape_count));
+        bytes.extend_from_slice(&self.comment);
+        try!(stream.write_all(&bytes));
+        Ok(())
+    }
+}
+
+impl SlpShapeHeader {
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> Result<()> {
+        let mut bytes = Vec::with_capacity(SHAPE_HEADER_SIZE as usize);
+        for &value in &[self.shape_data_offsets,
+                        self.shape_outline_offset,
+                        self.palette_offset,
+                        self.properties,
+                        self.width,
+                        self.height,
+                        self.center_x as u32,
+                        self.center_y as u32] {
+            bytes.extend_from_slice(&u32_bytes(value));
+        }
+        try!(stream.write_all(&bytes));
+        Ok(())
+    }
+}
+
+impl SlpFile {
+    /// Builds a 2.0N file from already-indexed frames.
+    ///
+    /// Pixels of a frame's `transparent_index` are skipped and all others are drawn as regular
+    /// colors. Fails if a frame's `indices` don't match its dimensions, or if `comment` is too
+    /// long for the header.
+    pub fn from_indexed_frames(frames: &[IndexedFrame], comment: &str) -> Result<SlpFile> {
+        let mut slp_file = SlpFile::new();
+        slp_file.header = try!(SlpHeader::with_version_and_comment("2.0N", comment));
+        for frame in frames {
+            let mut header = SlpShapeHeader::new();
+            header.width = frame.width;
+            header.height = frame.height;
+            header.center_x = frame.anchor.0;
+            header.center_y = frame.anchor.1;
+            let area = frame.width as usize * frame.height as usize;
+            if frame.indices.len() != area {
+                return Err(ErrorKind::InvalidSlp(format!("a {}x{} frame should have {} indices, \
+                                                          but has {}",
+                                                         frame.width,
+                                                         frame.height,
+                                                         area,
+                                                         frame.indices.len()))
+                    .into());
+            }
+            let transparent_index = frame.transparent_index;
+            let commands = match frame.width {
+                0 => vec![Vec::new(); frame.height as usize],
+                width => {
+                    frame.indices
+                        .chunks(width as usize)
+                        .map(|row| {
+                            row_commands(row.iter().map(|&index| if index == transparent_index {
+                                (PixelKind::Skip, 0)
+                            } else {
+                                (PixelKind::Color, index)
+                            }))
+                        })
+                        .collect()
+                }
+            };
+            let pixels = frame.indices
+                .iter()
+                .map(|&index| if index == transparent_index { 0 } else { index })
+                .collect();
+            let shape = try!(SlpLogicalShape::from_parts(header, pixels, commands));
+            slp_file.shapes.push(shape);
+        }
+        slp_file.header.shape_count = slp_file.shapes.len() as u32;
+        Ok(slp_file)
+    }
+
+    /// Encodes the file as a 2.0N SLP into `out`, starting at its current position.
+    ///
+    /// `shape_count` is written as the number of shapes, whatever the header says. Shapes that
+    /// haven't been modified since they were read keep their original encoding.
+    pub fn write_to<W: Write + Seek>(&self, out: &mut W) -> Result<()> {
+        self.write_to_with_options(out, &WriteOptions::default())
+    }
+
+    /// Like [write_to](#method.write_to), encoding modified shapes with `options`.
+    pub fn write_to_with_options<W: Write + Seek>(&self,
+                                                  out: &mut W,
+                                                  options: &WriteOptions)
+                                                  -> Result<()> {
+        let mut offset = SLP_HEADER_SIZE + SHAPE_HEADER_SIZE * self.shapes.len() as u64;
+        let mut encoded = Vec::with_capacity(self.shapes.len());
+        for shape in &self.shapes {
+            let (header, data) = try!(encode_shape_at(shape, try!(file_offset(offset)), options));
+            offset += data.len() as u64;
+            encoded.push((header, data));
+        }
+
+        let mut header = self.header.clone();
+        if header.extended.take().is_some() {
+            // Version 4 fields would be read back as the comment
+            header.comment = [0u8; 24];
+        }
+        header.file_version = *b"2.0N";
+        header.shape_count = self.shapes.len() as u32;
+        try!(header.write_to(out));
+        for (shape_header, _) in &encoded {
+            try!(shape_header.write_to(out));
+        }
+        for (_, data) in &encoded {
+            try!(out.write_all(data));
+        }
+        Ok(())
+    }
+
+    /// Writes the file to `file_name`, replacing anything already there.
+    pub fn write_to_file<P: AsRef<Path>>(&self, file_name: P) -> Result<()> {
+        let file = try!(File::create(file_name.as_ref()));
+        let mut out = BufWriter::new(file);
+        try!(self.write_to(&mut out));
+        try!(out.flush());
+        Ok(())
+    }
+
+    /// Returns the number of bytes this file will take up once encoded, without encoding it to
+    /// an output stream.
+    pub fn encoded_size(&self) -> Result<u64> {
+        self.encoded_size_with_options(&WriteOptions::default())
+    }
+
+    /// Like [encoded_size](#method.encoded_size), for encoding with `options`.
+    ///
+    /// Shapes that haven't been modified since they were read keep their original encoding
+    /// whatever the options say.
+    pub fn encoded_size_with_options(&self, options: &WriteOptions) -> Result<u64> {
+        let mut size = SLP_HEADER_SIZE + SHAPE_HEADER_SIZE * self.shapes.len() as u64;
+        for shape in &self.shapes {
+            size += try!(encoded_shape_size(shape, options));
+        }
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IndexedFrame, copy_size, encode_copy, encode_fill, encode_remap_copy,
+                encode_shape, encode_shape_at, file_offset, fill_size, remap_copy_size};
+    use error::{Error, ErrorKind};
+    use options::{OptimizeLevel, WriteOptions};
+    use slp::{DrawCommand, PixelKind, SlpFile, SlpLogicalShape};
+    use std::io;
+    use test_util::{build_slp, set_pixel_kinds, single_shape_slp};
+
+    #[test]
+    fn test_encode_shape() {
+        use slp::PixelKind::*;
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 10;
+        shape.header.height = 2;
+        shape.pixels = vec![0, 1, 2, 5, 5, 5, 5, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        set_pixel_kinds(&mut shape,
+                        &[Skip, Color, Color, Color, Color, Color, Color, Remap, Remap, Shadow,
+                          Skip, Skip, Skip, Skip, Skip, Skip, Skip, Skip, Skip, Skip]);
+
+        let rows = encode_shape(&shape, &WriteOptions::default()).unwrap();
+        assert_eq!(vec![0x08, 1, 2, 0x47, 5, 0x26, 3, 3, 0x1B, 0x0F], rows[0].commands);
+        assert_eq!((1, 0), (rows[0].left, rows[0].right));
+        assert_eq!(vec![0x0F], rows[1].commands);
+        assert_eq!((0x8000, 0x8000), (rows[1].left, rows[1].right));
+
+        let mut slp_file = SlpFile::new();
+        slp_file.shapes.push(shape);
+        assert_eq!(32 + 32 + 2 * 8 + 10 + 1, slp_file.encoded_size().unwrap());
+    }
+
+    #[test]
+    fn test_encode_shape_long_runs() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 400;
+        shape.header.height = 1;
+        shape.pixels = (0..400).map(|i| (i % 2) as u8).collect();
+        let mut kinds = vec![PixelKind::Color; 400];
+        kinds[0] = PixelKind::Skip;
+        kinds[1] = PixelKind::Skip;
+        set_pixel_kinds(&mut shape, &kinds);
+
+        let row = &encode_shape(&shape, &WriteOptions::default()).unwrap()[0].commands;
+        // A large block copy of 398 pixels, then the end of line
+        assert_eq!(&[0x12, (398 & 0xFF) as u8], &row[..2]);
+        assert_eq!(2 + 398 + 1, row.len());
+    }
+
+    #[test]
+    fn test_encoded_size_rejects_bad_buffers() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 2;
+        shape.header.height = 2;
+        let mut slp_file = SlpFile::new();
+        slp_file.shapes.push(shape);
+        assert!(slp_file.encoded_size().is_err());
+    }
+
+    #[test]
+    fn test_encoded_sizes() {
+        for &length in &[1, 15, 16, 63, 64, 255, 256, 0xFFF, 0x1000, 10000] {
+            let pixels = vec![0u8; length];
+            let mut out = Vec::new();
+            encode_copy(&mut out, &pixels);
+            assert_eq!(out.len(), copy_size(length));
+
+            out.clear();
+            encode_remap_copy(&mut out, &pixels);
+            assert_eq!(out.len(), remap_copy_size(length));
+
+            out.clear();
+            encode_fill(&mut out, 0x07, 0, length);
+            assert_eq!(out.len(), fill_size(length));
+        }
+    }
+
+    #[test]
+    fn test_encode_shape_optimize_levels() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 65;
+        shape.header.height = 1;
+        // 60 distinct values, then a run of three and a run of two
+        shape.pixels = (0..60).collect();
+        shape.pixels.extend_from_slice(&[200, 200, 200, 201, 201]);
+        set_pixel_kinds(&mut shape, &[PixelKind::Color; 65]);
+
+        let row_size = |optimize| {
+            let options = WriteOptions { optimize };
+            encode_shape(&shape, &options).unwrap()[0].commands.len()
+        };
+        // One large copy
+        assert_eq!(2 + 65 + 1, row_size(OptimizeLevel::None));
+        // A copy, a fill, and a copy of the last two
+        assert_eq!(1 + 60 + 2 + 3 + 1, row_size(OptimizeLevel::Normal));
+        // Filling the last two too keeps the copy small
+        assert_eq!(1 + 60 + 2 + 2 + 1, row_size(OptimizeLevel::Max));
+
+        let mut slp_file = SlpFile::new();
+        slp_file.shapes.push(shape);
+        let options = WriteOptions { optimize: OptimizeLevel::Max };
+        assert_eq!(32 + 32 + 8 + 66, slp_file.encoded_size_with_options(&options).unwrap());
+    }
+
+    #[test]
+    fn test_encode_shape_raw_commands() {
+        use slp::RawCommand;
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 5;
+        shape.header.height = 1;
+        shape.pixels = vec![7, 0, 0, 0, 0];
+        shape.commands = vec![vec![DrawCommand::Color { count: 1 }, DrawCommand::Skip { count: 4 }]];
+        shape.raw_commands = vec![RawCommand { x: 1, y: 0, length: 1, bytes: vec![0x4E] },
+                                  RawCommand { x: 2, y: 0, length: 2, bytes: vec![0x7E, 2] }];
+
+        let row = &encode_shape(&shape, &WriteOptions::default()).unwrap()[0];
+        assert_eq!(vec![0x04, 7, 0x4E, 0x7E, 2, 0x0F], row.commands);
+        assert_eq!((0, 1), (row.left, row.right));
+    }
+
+    #[test]
+    fn test_slp_file_from_indexed_frames() {
+        let frame = IndexedFrame {
+            width: 3,
+            height: 2,
+            indices: vec![0xFF, 4, 5, 6, 0xFF, 0xFF],
+            transparent_index: 0xFF,
+            anchor: (1, 2),
+        };
+        let slp_file = SlpFile::from_indexed_frames(&[frame.clone(), frame.clone()], "test")
+            .unwrap();
+        assert_eq!("test", slp_file.header.comment_str());
+        assert_eq!(2, slp_file.header.shape_count);
+        let shape = &slp_file.shapes[1];
+        assert_eq!((1, 2), shape.anchor());
+        assert_eq!(vec![0, 4, 5, 6, 0, 0], shape.pixels);
+        assert_eq!(vec![vec![DrawCommand::Skip { count: 1 }, DrawCommand::Color { count: 2 }],
+                        vec![DrawCommand::Color { count: 1 }, DrawCommand::Skip { count: 2 }]],
+                   shape.commands);
+
+        let mut bad_frame = frame;
+        bad_frame.indices.pop();
+        assert!(SlpFile::from_indexed_frames(&[bad_frame], "").is_err());
+    }
+
+    #[test]
+    fn test_slp_file_write_to() {
+        let data = build_slp(&[(3, &[(1, 0, &[0x08, 7, 8, 0x0F]), (0x8000, 0x8000, &[])]),
+                               (2, &[(0, 0, &[0x08, 5, 6, 0x0F])])]);
+        let mut slp_file = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+
+        // Unmodified shapes are written back byte for byte
+        let mut written = io::Cursor::new(Vec::new());
+        slp_file.write_to(&mut written).unwrap();
+        assert_eq!(data, written.into_inner());
+
+        slp_file.shapes[1].pixels = vec![9, 0];
+        slp_file.shapes[1].commands[0] = vec![DrawCommand::Color { count: 1 },
+                                              DrawCommand::Shadow { count: 1 }];
+        let mut written = io::Cursor::new(Vec::new());
+        slp_file.write_to(&mut written).unwrap();
+        assert_eq!(slp_file.encoded_size().unwrap(), written.get_ref().len() as u64);
+
+        written.set_position(0);
+        let reread = SlpFile::read_from(&mut written).unwrap();
+        assert_eq!(2, reread.header.shape_count);
+        for (shape, reread_shape) in slp_file.shapes.iter().zip(reread.shapes.iter()) {
+            assert_eq!(shape.pixels, reread_shape.pixels);
+            assert_eq!(shape.commands, reread_shape.commands);
+        }
+    }
+
+    #[test]
+    fn test_encode_shape_at_past_4_gib() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 1;
+        shape.header.height = 1;
+        shape.pixels = vec![7];
+        set_pixel_kinds(&mut shape, &[PixelKind::Color]);
+
+        // 8 bytes of tables and 3 of commands
+        let options = WriteOptions::default();
+        assert!(encode_shape_at(&shape, u32::MAX - 11, &options).is_ok());
+        match encode_shape_at(&shape, u32::MAX - 10, &options) {
+            Err(Error(ErrorKind::InvalidSlp(_), _)) => {}
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
+        }
+        assert!(file_offset(u32::MAX as u64 + 1).is_err());
+    }
+
+    #[test]
+    fn test_slp_file_write_to_scattered_rows() {
+        // The only row's commands are far from the shape's tables
+        let mut data = single_shape_slp(1, &[(0, 0, &[])]);
+        data.truncate(72);
+        data.extend_from_slice(&[0u8; 200]);
+        data[68..72].copy_from_slice(&[16, 1, 0, 0]);
+        data.extend_from_slice(&[0x04, 7, 0x0F]);
+
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        assert!(slp_file.shapes[0].raw.is_none());
+
+        // So the shape is re-encoded rather than copied
+        let mut written = io::Cursor::new(Vec::new());
+        slp_file.write_to(&mut written).unwrap();
+        assert_eq!(32 + 32 + 8 + 3, written.get_ref().len());
+        written.set_position(0);
+        assert_eq!(vec![7], SlpFile::read_from(&mut written).unwrap().shapes[0].pixels);
+    }
+
+    #[test]
+    fn test_slp_file_write_to_relative_indices() {
+        // Relative indices are a whole byte, not just the 16 entries of a player's block
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 2;
+        shape.header.height = 1;
+        shape.pixels = vec![20, 3];
+        shape.commands = vec![vec![DrawCommand::PlayerColor { relative: vec![20, 3] }]];
+        let mut slp_file = SlpFile::new();
+        slp_file.shapes.push(shape);
+
+        let mut written = io::Cursor::new(Vec::new());
+        slp_file.write_to(&mut written).unwrap();
+        written.set_position(0);
+        let reread = SlpFile::read_from(&mut written).unwrap();
+        assert_eq!(vec![20, 3], reread.shapes[0].pixels);
+        assert_eq!(slp_file.shapes[0].commands, reread.shapes[0].commands);
+    }
+
+    #[test]
+    fn test_slp_file_write_to_baked() {
+        let data = single_shape_slp(3, &[(0, 0, &[0x26, 20, 3, 0x04, 7, 0x0F])]);
+        let mut slp_file = SlpFile::read_from_baked(&mut io::Cursor::new(&data), 2).unwrap();
+        assert_eq!(vec![16 * 2 + 20, 16 * 2 + 3, 7], slp_file.shapes[0].pixels);
+
+        // Player colors are written from their run, whatever the pixels were resolved to
+        slp_file.shapes[0].swap_index_range(7..8, 8);
+        let mut written = io::Cursor::new(Vec::new());
+        slp_file.write_to(&mut written).unwrap();
+        written.set_position(0);
+        let reread = SlpFile::read_from(&mut written).unwrap();
+        assert_eq!(vec![20, 3, 8], reread.shapes[0].pixels);
+    }
+
+    #[test]
+    fn test_slp_file_write_to_edited_pixels() {
+        use rgba::PixelFormat;
+        use runs::RunKind;
+        let data = single_shape_slp(3, &[(0, 0, &[0x08, 7, 8, 0x17, 5, 0x0F])]);
+        let mut slp_file = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+
+        // Colors are only kept in the pixels, so every reader sees edits to them
+        slp_file.shapes[0].pixels[1] = 9;
+        slp_file.shapes[0].pixels[2] = 6;
+        let shape = &slp_file.shapes[0];
+        let mut runs = Vec::new();
+        shape.for_each_run(|kind, _, _, indices| runs.push((kind, indices.to_vec())));
+        assert_eq!(vec![(RunKind::Color, vec![7, 9]), (RunKind::Fill, vec![6])], runs);
+        let histogram = shape.index_histogram();
+        assert_eq!((1, 0, 1, 1), (histogram[9], histogram[8], histogram[7], histogram[6]));
+        let mut palette = [[0u8; 3]; 256];
+        palette[9] = [1, 2, 3];
+        assert_eq!(&[1, 2, 3, 255],
+                   &shape.to_rgba(&palette, 1, PixelFormat::Rgba)[4..8]);
+
+        let mut written = io::Cursor::new(Vec::new());
+        slp_file.write_to(&mut written).unwrap();
+        written.set_position(0);
+        let reread = SlpFile::read_from(&mut written).unwrap();
+        assert_eq!(vec![7, 9, 6], reread.shapes[0].pixels);
+    }
+
+    #[test]
+    fn test_encode_shape_outlines() {
+        use slp::PixelKind::*;
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 6;
+        shape.header.height = 1;
+        shape.pixels = vec![0; 6];
+        set_pixel_kinds(&mut shape, &[Outline, BlackOutline, BlackOutline, Skip, Outline, Outline]);
+
+        let rows = encode_shape(&shape, &WriteOptions::default()).unwrap();
+        assert_eq!(vec![0x4E, 0x7E, 2, 0x05, 0x5E, 2, 0x0F], rows[0].commands);
+    }
+}
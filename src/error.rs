@@ -39,9 +39,38 @@ error_chain! {
             description("invalid SLP")
             display("invalid SLP: {}", reason)
         }
-        BadLength {
+        DestinationTooSmall(needed: usize, actual: usize) {
+            description("destination buffer too small")
+            display("destination buffer too small: needed {} bytes but got {}", needed, actual)
+        }
+        DecodeError(shape: usize, row: u32, reason: String) {
+            description("invalid SLP shape")
+            display("invalid SLP: shape {}, row {}: {}", shape, row, reason)
+        }
+        ShapeReadError(shape: usize, row: u32) {
+            description("failed to read SLP shape")
+            display("failed to read shape {} at row {}", shape, row)
+        }
+        BadLength(cmd_byte: u8, row: u32, x: u32) {
             description("bad length in SLP")
-            display("bad length in SLP")
+            display("bad length in {} command ({:#04x}) at row {}, x {}",
+                    ::slp::command_name(*cmd_byte), cmd_byte, row, x)
+        }
+        Aborted {
+            description("decoding was aborted")
+            display("decoding was aborted")
+        }
+        BadPaletteLength(length: usize) {
+            description("bad palette length")
+            display("palette should be 768 bytes of RGB, but was {} bytes", length)
+        }
+        InvalidHeader(reason: String) {
+            description("invalid SLP header")
+            display("invalid SLP header: {}", reason)
+        }
+        UnsupportedVersion(version: String) {
+            description("unsupported SLP version")
+            display("unsupported SLP version: {:?}", version)
         }
     }
 }
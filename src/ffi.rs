@@ -0,0 +1,433 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+//! C bindings, enabled with the `ffi` feature.
+//!
+//! Functions return a negative value on failure; nothing is written through the output pointers
+//! in that case. A description of the failure can then be fetched with
+//! [slp_last_error_message](fn.slp_last_error_message.html).
+
+use error::ErrorKind;
+use rgba::{Palette, PixelFormat};
+use slp::SlpFile;
+use std::cell::RefCell;
+use std::cmp;
+use std::ffi::CStr;
+use std::fs::File;
+use std::mem;
+use std::os::raw::c_char;
+use std::ptr;
+use std::slice;
+
+/// The file couldn't be opened or decoded, or an argument was invalid.
+pub const ERR_FAILED: isize = -1;
+
+/// The palette passed in wasn't 768 bytes of RGB.
+pub const ERR_BAD_PALETTE: isize = -2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Records `message` as this thread's last error and returns `code`.
+fn fail(code: isize, message: String) -> isize {
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+    code
+}
+
+/// Copies a description of the last failure on this thread into `buf` as a NUL terminated
+/// string, truncating it to fit in `buf_len` bytes.
+///
+/// Returns the length of the whole description, not counting the NUL, or 0 if nothing has
+/// failed yet. `buf` may be null if `buf_len` is 0, to just ask for the length.
+///
+/// # Safety
+///
+/// Unless it's null, `buf` must point to at least `buf_len` writable bytes. They still belong
+/// to the caller afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn slp_last_error_message(buf: *mut c_char, buf_len: usize) -> isize {
+    if buf.is_null() && buf_len > 0 {
+        return ERR_FAILED;
+    }
+    LAST_ERROR.with(|last_error| {
+        let last_error = last_error.borrow();
+        let message = match *last_error {
+            Some(ref message) => message.as_bytes(),
+            None => &[],
+        };
+        if buf_len > 0 {
+            let copied = cmp::min(message.len(), buf_len - 1);
+            ptr::copy_nonoverlapping(message.as_ptr() as *const c_char, buf, copied);
+            *buf.add(copied) = 0;
+        }
+        message.len() as isize
+    })
+}
+
+/// File-wide metadata filled in by [slp_metadata_from_file](fn.slp_metadata_from_file.html).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlpMetaC {
+    /// The raw version bytes, such as `2.0N`; not NUL terminated.
+    pub version: [u8; 4],
+    pub frame_count: u32,
+}
+
+/// Per-frame metadata filled in by
+/// [slp_frame_metadata_from_file](fn.slp_frame_metadata_from_file.html).
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlpFrameMetaC {
+    pub width: u32,
+    pub height: u32,
+    pub center_x: i32,
+    pub center_y: i32,
+}
+
+/// Reads the header of the SLP at the NUL terminated `path` into `out_meta` without decoding
+/// any pixels.
+///
+/// Returns the number of frames, or -1 on failure.
+///
+/// # Safety
+///
+/// `path` must be null or a NUL terminated string, and `out_meta` must be null or point to a
+/// writable `SlpMetaC`. Both still belong to the caller afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn slp_metadata_from_file(path: *const c_char,
+                                                out_meta: *mut SlpMetaC)
+                                                -> isize {
+    if out_meta.is_null() {
+        return fail(ERR_FAILED, "out_meta is null".into());
+    }
+    match read_headers(path) {
+        Ok((meta, _)) => {
+            ptr::write(out_meta, meta);
+            meta.frame_count as isize
+        }
+        Err(message) => fail(ERR_FAILED, message),
+    }
+}
+
+/// Writes the metadata of up to `capacity` frames of the SLP at `path` into the
+/// caller-allocated `out_frames` array.
+///
+/// Returns the total number of frames in the file, which may be more than `capacity`, or -1 on
+/// failure. `out_frames` may be null if `capacity` is 0, to just ask for the frame count.
+///
+/// # Safety
+///
+/// `path` must be null or a NUL terminated string, and unless it's null, `out_frames` must
+/// point to an array of at least `capacity` writable `SlpFrameMetaC`s. Both still belong to the
+/// caller afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn slp_frame_metadata_from_file(path: *const c_char,
+                                                      out_frames: *mut SlpFrameMetaC,
+                                                      capacity: usize)
+                                                      -> isize {
+    if out_frames.is_null() && capacity > 0 {
+        return fail(ERR_FAILED, "out_frames is null".into());
+    }
+    match read_headers(path) {
+        Ok((meta, frames)) => {
+            for (index, frame) in frames.into_iter().take(capacity).enumerate() {
+                ptr::write(out_frames.add(index), frame);
+            }
+            meta.frame_count as isize
+        }
+        Err(message) => fail(ERR_FAILED, message),
+    }
+}
+
+/// A decoded frame handed out by [slp_new_from_file_all](fn.slp_new_from_file_all.html).
+#[repr(C)]
+#[derive(Debug)]
+pub struct SlpShapeC {
+    /// `width * height` pixels in row-major order, each `bytes_per_pixel` bytes long.
+    pub pixels: *mut u8,
+
+    /// 1 for palette indices, or 4 for straight RGBA.
+    pub bytes_per_pixel: u32,
+    pub width: u32,
+    pub height: u32,
+    pub center_x: i32,
+    pub center_y: i32,
+}
+
+/// Decodes every frame of the SLP at `path`, with `player_index`'s colors baked in, and
+/// stores an array of them in `out_shapes`.
+///
+/// Frames are palette indices, unless `rgba` is nonzero and `palette_rgb` points to
+/// `palette_len` bytes of RGB, in which case they're resolved to straight RGBA. A
+/// `palette_len` other than 768 fails with `ERR_BAD_PALETTE`.
+///
+/// Returns the number of frames, or a negative error code. On success the array and every
+/// frame's `pixels` belong to the caller, who must release them all at once by passing the
+/// array and count to [slp_free_all](fn.slp_free_all.html); they must not be freed any other
+/// way.
+///
+/// # Safety
+///
+/// `path` must be null or a NUL terminated string, `palette_rgb` must be null or point to at
+/// least `palette_len` readable bytes, and `out_shapes` must be null or point to a writable
+/// pointer. All three still belong to the caller afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn slp_new_from_file_all(path: *const c_char,
+                                               player_index: u8,
+                                               palette_rgb: *const u8,
+                                               palette_len: usize,
+                                               rgba: u8,
+                                               out_shapes: *mut *mut SlpShapeC)
+                                               -> isize {
+    if out_shapes.is_null() {
+        return fail(ERR_FAILED, "out_shapes is null".into());
+    }
+    let palette = if rgba != 0 && !palette_rgb.is_null() {
+        match Palette::from_rgb(slice::from_raw_parts(palette_rgb, palette_len)) {
+            Ok(palette) => Some(palette),
+            Err(err) => {
+                let code = match *err.kind() {
+                    ErrorKind::BadPaletteLength(_) => ERR_BAD_PALETTE,
+                    _ => ERR_FAILED,
+                };
+                return fail(code, err.to_string());
+            }
+        }
+    } else {
+        None
+    };
+    let path = match path_str(path) {
+        Ok(path) => path,
+        Err(message) => return fail(ERR_FAILED, message),
+    };
+    let slp_file = match SlpFile::read_from_file_baked(path, player_index) {
+        Ok(slp_file) => slp_file,
+        Err(err) => return fail(ERR_FAILED, err.to_string()),
+    };
+
+    let shapes: Vec<SlpShapeC> = slp_file.shapes
+        .into_iter()
+        .map(|shape| {
+            let (pixels, bytes_per_pixel) = match palette {
+                Some(ref palette) => (shape.to_rgba(palette, player_index, PixelFormat::Rgba), 4),
+                None => (shape.pixels, 1),
+            };
+            SlpShapeC {
+                pixels: Box::into_raw(pixels.into_boxed_slice()) as *mut u8,
+                bytes_per_pixel,
+                width: shape.header.width,
+                height: shape.header.height,
+                center_x: shape.header.center_x,
+                center_y: shape.header.center_y,
+            }
+        })
+        .collect();
+    let mut shapes = shapes.into_boxed_slice();
+    let count = shapes.len();
+    ptr::write(out_shapes, shapes.as_mut_ptr());
+    mem::forget(shapes);
+    count as isize
+}
+
+/// Frees an array of `count` frames returned by
+/// [slp_new_from_file_all](fn.slp_new_from_file_all.html), along with their pixels.
+///
+/// Does nothing if `shapes` is null.
+///
+/// # Safety
+///
+/// `shapes` must be null or an array returned by `slp_new_from_file_all` that hasn't been freed
+/// yet, and `count` must be the frame count that call returned. Neither the array nor any
+/// frame's `pixels` may be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn slp_free_all(shapes: *mut SlpShapeC, count: usize) {
+    if shapes.is_null() {
+        return;
+    }
+    let shapes = Box::from_raw(ptr::slice_from_raw_parts_mut(shapes, count));
+    for shape in shapes.iter() {
+        let len = shape.width as usize * shape.height as usize * shape.bytes_per_pixel as usize;
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(shape.pixels, len)));
+    }
+}
+
+unsafe fn path_str<'a>(path: *const c_char) -> ::std::result::Result<&'a str, String> {
+    if path.is_null() {
+        return Err("path is null".into());
+    }
+    CStr::from_ptr(path).to_str().map_err(|_| "path isn't valid UTF-8".into())
+}
+
+unsafe fn read_headers(path: *const c_char)
+                       -> ::std::result::Result<(SlpMetaC, Vec<SlpFrameMetaC>), String> {
+    let path = try!(path_str(path));
+    let mut file = try!(File::open(path).map_err(|err| err.to_string()));
+    let (header, shape_headers) = try!(SlpFile::read_shape_headers(&mut file)
+        .map_err(|err| err.to_string()));
+
+    let meta = SlpMetaC {
+        version: header.file_version,
+        frame_count: header.shape_count,
+    };
+    let frames = shape_headers.iter()
+        .map(|shape_header| {
+            SlpFrameMetaC {
+                width: shape_header.width,
+                height: shape_header.height,
+                center_x: shape_header.center_x,
+                center_y: shape_header.center_y,
+            }
+        })
+        .collect();
+    Ok((meta, frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ERR_BAD_PALETTE, ERR_FAILED, SlpFrameMetaC, SlpMetaC, SlpShapeC,
+                slp_frame_metadata_from_file, slp_free_all, slp_last_error_message,
+                slp_metadata_from_file, slp_new_from_file_all};
+    use std::slice;
+    use std::env;
+    use std::ffi::{CStr, CString};
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::os::raw::c_char;
+    use std::ptr;
+    use test_util::{build_slp, single_shape_slp};
+
+    #[test]
+    fn test_slp_metadata_from_file() {
+        let data = single_shape_slp(3, &[(0, 0, &[0x0C, 1, 2, 3, 0x0F])]);
+        let path = env::temp_dir().join("chariot_slp_test_ffi_metadata.slp");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        let mut meta = SlpMetaC { version: [0; 4], frame_count: 0 };
+        assert_eq!(1, unsafe { slp_metadata_from_file(c_path.as_ptr(), &mut meta) });
+        assert_eq!(SlpMetaC { version: *b"2.0N", frame_count: 1 }, meta);
+
+        let mut frames = [SlpFrameMetaC { width: 0, height: 0, center_x: 0, center_y: 0 }; 2];
+        let count = unsafe { slp_frame_metadata_from_file(c_path.as_ptr(), frames.as_mut_ptr(), 2) };
+        assert_eq!(1, count);
+        assert_eq!(3, frames[0].width);
+        assert_eq!(1, frames[0].height);
+        assert_eq!(0, frames[1].width);
+
+        let count = unsafe { slp_frame_metadata_from_file(c_path.as_ptr(), ptr::null_mut(), 0) };
+        assert_eq!(1, count);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_slp_metadata_from_file_failure() {
+        let missing = CString::new("/nonexistent/chariot_slp_test.slp").unwrap();
+        let mut meta = SlpMetaC { version: [0; 4], frame_count: 7 };
+        assert_eq!(-1, unsafe { slp_metadata_from_file(missing.as_ptr(), &mut meta) });
+        assert_eq!(-1, unsafe { slp_metadata_from_file(ptr::null(), &mut meta) });
+        assert_eq!(7, meta.frame_count);
+    }
+
+    #[test]
+    fn test_slp_new_from_file_all() {
+        let data = build_slp(&[(1, &[(0, 0, &[0x04, 7, 0x0F])]),
+                               (2, &[(0x8000, 0x8000, &[]), (0, 0, &[0x08, 8, 9, 0x0F])])]);
+        let path = env::temp_dir().join("chariot_slp_test_ffi_all.slp");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        let mut shapes: *mut SlpShapeC = ptr::null_mut();
+        let count = unsafe {
+            slp_new_from_file_all(c_path.as_ptr(), 1, ptr::null(), 0, 0, &mut shapes)
+        };
+        assert_eq!(2, count);
+        unsafe {
+            let frames = slice::from_raw_parts(shapes, count as usize);
+            assert_eq!((2, 2, 1), (frames[1].width, frames[1].height, frames[1].bytes_per_pixel));
+            assert_eq!(&[0, 0, 8, 9], slice::from_raw_parts(frames[1].pixels, 4));
+            assert_eq!(&[7], slice::from_raw_parts(frames[0].pixels, 1));
+            slp_free_all(shapes, count as usize);
+        }
+
+        let palette: Vec<u8> = (0..768).map(|i| (i / 3) as u8).collect();
+        let mut shapes: *mut SlpShapeC = ptr::null_mut();
+        let count = unsafe {
+            slp_new_from_file_all(c_path.as_ptr(), 1, palette.as_ptr(), 768, 1, &mut shapes)
+        };
+        assert_eq!(2, count);
+        unsafe {
+            let frames = slice::from_raw_parts(shapes, count as usize);
+            assert_eq!(4, frames[0].bytes_per_pixel);
+            assert_eq!(&[7, 7, 7, 255], slice::from_raw_parts(frames[0].pixels, 4));
+            slp_free_all(shapes, count as usize);
+        }
+
+        let mut shapes: *mut SlpShapeC = ptr::null_mut();
+        let result = unsafe {
+            slp_new_from_file_all(c_path.as_ptr(), 1, palette.as_ptr(), 767, 1, &mut shapes)
+        };
+        assert_eq!(ERR_BAD_PALETTE, result);
+        assert_eq!("palette should be 768 bytes of RGB, but was 767 bytes", last_error_message());
+        fs::remove_file(&path).unwrap();
+
+        let missing = CString::new("/nonexistent/chariot_slp_test.slp").unwrap();
+        let result = unsafe {
+            slp_new_from_file_all(missing.as_ptr(), 1, ptr::null(), 0, 0, &mut shapes)
+        };
+        assert_eq!(ERR_FAILED, result);
+        assert!(shapes.is_null());
+    }
+
+    fn last_error_message() -> String {
+        let mut buf = [0 as c_char; 256];
+        let length = unsafe { slp_last_error_message(buf.as_mut_ptr(), buf.len()) };
+        let message = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap().to_string();
+        assert_eq!(length as usize, message.len());
+        message
+    }
+
+    #[test]
+    fn test_slp_last_error_message() {
+        let data = single_shape_slp(1, &[(0, 0, &[0xFE, 0x0F])]);
+        let path = env::temp_dir().join("chariot_slp_test_ffi_error.slp");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+        let mut shapes: *mut SlpShapeC = ptr::null_mut();
+        let result = unsafe {
+            slp_new_from_file_all(c_path.as_ptr(), 1, ptr::null(), 0, 0, &mut shapes)
+        };
+        assert_eq!(ERR_FAILED, result);
+        fs::remove_file(&path).unwrap();
+        let message = last_error_message();
+        assert!(message.contains("unknown command byte 0xfe"), "{}", message);
+
+        // Truncated to fit, and the full length is still reported
+        let mut buf = [0x7F as c_char; 8];
+        let length = unsafe { slp_last_error_message(buf.as_mut_ptr(), buf.len()) };
+        assert_eq!(message.len() as isize, length);
+        assert_eq!(&message[..7], unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap());
+        assert_eq!(length, unsafe { slp_last_error_message(ptr::null_mut(), 0) });
+    }
+}
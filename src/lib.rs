@@ -51,15 +51,78 @@ extern crate error_chain;
 
 extern crate chariot_io_tools;
 
+#[cfg(feature = "memmap")]
+extern crate memmap;
+
+#[cfg(feature = "png")]
+extern crate png;
+
+#[cfg(feature = "parallel")]
+extern crate rayon;
+
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+mod atlas;
+mod builder;
+mod cache;
+mod diff;
+mod disasm;
+mod encode;
 mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod options;
+mod packed;
+#[cfg(feature = "png")]
+mod png_export;
+mod rgba;
+mod runs;
+#[cfg(feature = "serde")]
+mod serialize;
 mod slp;
+mod stream;
+
+#[cfg(test)]
+mod test_util;
 
+pub use atlas::AtlasAllocator;
+pub use builder::SlpBuilder;
+pub use cache::DecodedFrameCache;
+pub use diff::Difference;
+pub use encode::IndexedFrame;
 pub use error::ChainErr;
 pub use error::Error;
 pub use error::ErrorKind;
 pub use error::Result;
+pub use options::DEFAULT_MAX_DIMENSION;
+pub use options::DecodeOptions;
+pub use options::OptimizeLevel;
+pub use options::PixelLayout;
+pub use options::WriteOptions;
+pub use packed::PackedFrame;
+pub use rgba::Palette;
+pub use rgba::PixelFormat;
+pub use rgba::SHADOW_ALPHA;
+pub use runs::RunKind;
+pub use slp::DEFAULT_PALETTE_ID;
+pub use slp::DrawCommand;
+pub use slp::NO_PLAYER;
+pub use slp::PixelKind;
+pub use slp::RawCommand;
 pub use slp::SlpFile;
 pub use slp::SlpHeader;
 pub use slp::SlpLogicalShape;
+pub use slp::SlpDrawCommands;
+pub use slp::SlpExtendedHeader;
 pub use slp::SlpPixels;
+pub use slp::SlpRawShape;
 pub use slp::SlpShapeHeader;
+pub use slp::SlpVersion;
+pub use slp::decode_frame_from_slice;
+pub use slp::default_palette_id_for;
+pub use stream::SlpStreamWriter;
@@ -0,0 +1,144 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+/// Frames wider or taller than this are rejected by default.
+///
+/// Real sprites are well under this; anything larger is most likely a corrupt or crafted header.
+pub const DEFAULT_MAX_DIMENSION: u32 = 8192;
+
+/// Settings for [SlpFile::read_from_with_options](struct.SlpFile.html#method.read_from_with_options).
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    /// The largest width or height a shape may have.
+    ///
+    /// Shapes exceeding it fail with `InvalidSlp` before their pixel buffers are allocated.
+    pub max_dimension: u32,
+
+    /// The order decoded pixels are stored in.
+    pub layout: PixelLayout,
+
+    /// Accepts rows whose commands draw exactly one pixel into the right padding, as long as
+    /// they still fit in the shape.
+    ///
+    /// Some files in the wild were written by an encoder that counted the padding one pixel
+    /// short. Without this, their rows fail with `DecodeError`.
+    pub lenient_row_end: bool,
+
+    /// The command byte that ends a row. Standard files use `0x0F`.
+    ///
+    /// When this is `0x0F`, any byte with `0xF` in its low 4 bits ends a row, as some encoders
+    /// set the upper bits.
+    pub end_of_line: u8,
+
+    /// The outline table value marking a row with nothing drawn in it. Standard files use
+    /// `0x8000`.
+    pub transparent_row: u16,
+
+    /// Added to every player color index, for palettes whose player colors don't start at 0.
+    ///
+    /// Ignored when decoding for [NO_PLAYER](constant.NO_PLAYER.html).
+    pub player_color_base: u8,
+
+    /// Fails with `InvalidSlp` if anything follows the last byte used by the headers or any
+    /// shape, which can mean the file is corrupt or two files were concatenated.
+    ///
+    /// Off by default, since some files legitimately carry extra data at the end.
+    pub reject_trailing_data: bool,
+}
+
+/// How a shape's `pixels` are ordered. Its `commands` are always kept row by row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelLayout {
+    /// Rows one after another; pixel `(x, y)` is at `y * width + x`.
+    RowMajor,
+
+    /// Columns one after another; pixel `(x, y)` is at `x * height + y`, so the stride between
+    /// columns is `height`.
+    ///
+    /// Everything else in this crate expects row-major shapes, so only use this when handing the
+    /// buffers straight to something that wants columns, such as a rotated texture upload.
+    ColumnMajor,
+}
+
+impl PixelLayout {
+    /// Returns where pixel `(x, y)` of a `width` by `height` shape is stored.
+    pub fn index(self, x: u32, y: u32, width: u32, height: u32) -> usize {
+        match self {
+            PixelLayout::RowMajor => (y * width + x) as usize,
+            PixelLayout::ColumnMajor => (x * height + y) as usize,
+        }
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        DecodeOptions {
+            max_dimension: DEFAULT_MAX_DIMENSION,
+            layout: PixelLayout::RowMajor,
+            lenient_row_end: false,
+            end_of_line: 0x0F,
+            transparent_row: 0x8000,
+            player_color_base: 0,
+            reject_trailing_data: false,
+        }
+    }
+}
+
+/// How hard the encoder tries to make shapes small.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptimizeLevel {
+    /// Copies every drawn run of pixels as-is, without looking for repeated values.
+    None,
+
+    /// Fills runs of three or more identical pixels and copies everything else.
+    Normal,
+
+    /// Searches for the combination of fills and copies that takes the fewest bytes.
+    ///
+    /// This takes time quadratic in the number of runs in a row, so it's meant for one-off
+    /// conversions rather than interactive editing.
+    Max,
+}
+
+/// Settings for encoding shapes.
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub optimize: OptimizeLevel,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions { optimize: OptimizeLevel::Normal }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PixelLayout;
+
+    #[test]
+    fn test_pixel_layout_index() {
+        assert_eq!(7, PixelLayout::RowMajor.index(1, 2, 3, 4));
+        assert_eq!(6, PixelLayout::ColumnMajor.index(1, 2, 3, 4));
+    }
+}
@@ -0,0 +1,172 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use slp::{PixelKind, SlpLogicalShape, remap_index};
+
+/// A compact, read-only copy of an [SlpLogicalShape](struct.SlpLogicalShape.html)'s indexed image.
+///
+/// Only pixels with a palette index (`Color` and `Remap`) are stored. `mask` holds one bit per
+/// pixel in row-major order (least significant bit first), and `indices` holds the palette
+/// index of every set bit, in the same order. `player_colors` has the same layout as `mask`,
+/// with the bits of `Remap` pixels set, whose entry in `indices` is their relative index.
+/// Shadow and outline pixels are treated as transparent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub mask: Vec<u8>,
+    pub player_colors: Vec<u8>,
+    pub indices: Vec<u8>,
+}
+
+impl PackedFrame {
+    /// Returns true if the pixel at `(x, y)` has a palette index.
+    pub fn is_drawn(&self, x: u32, y: u32) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let bit = (y * self.width + x) as usize;
+        self.mask[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    /// Returns true if the pixel at `(x, y)` is a player color.
+    pub fn is_player_color(&self, x: u32, y: u32) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let bit = (y * self.width + x) as usize;
+        self.player_colors[bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    /// Expands the frame into `dst`, an indexed image `dst_width` pixels wide, with its top-left
+    /// corner at `(x, y)`.
+    ///
+    /// Only drawn pixels are written, and anything falling outside `dst` is clipped. Player
+    /// colors are moved into `player_index`'s block of 16 palette entries, or written as they
+    /// are for `NO_PLAYER`.
+    pub fn blit(&self, dst: &mut [u8], dst_width: u32, x: u32, y: u32, player_index: u8) {
+        if dst_width == 0 {
+            return;
+        }
+        let dst_height = (dst.len() / dst_width as usize) as u32;
+        let mut next_index = 0;
+        for src_y in 0..self.height {
+            for src_x in 0..self.width {
+                if !self.is_drawn(src_x, src_y) {
+                    continue;
+                }
+                let mut index = self.indices[next_index];
+                next_index += 1;
+                if self.is_player_color(src_x, src_y) {
+                    index = remap_index(player_index, index, 0);
+                }
+
+                let (dst_x, dst_y) = match (x.checked_add(src_x), y.checked_add(src_y)) {
+                    (Some(dst_x), Some(dst_y)) => (dst_x, dst_y),
+                    _ => continue,
+                };
+                if dst_x < dst_width && dst_y < dst_height {
+                    dst[dst_y as usize * dst_width as usize + dst_x as usize] = index;
+                }
+            }
+        }
+    }
+}
+
+impl SlpLogicalShape {
+    /// Packs the shape into a [PackedFrame](struct.PackedFrame.html).
+    pub fn to_packed(&self) -> PackedFrame {
+        let area = self.header.width as usize * self.header.height as usize;
+        let mut packed = PackedFrame {
+            width: self.header.width,
+            height: self.header.height,
+            mask: vec![0u8; (area + 7) / 8],
+            player_colors: vec![0u8; (area + 7) / 8],
+            indices: Vec::new(),
+        };
+        for (bit, (kind, index)) in self.drawn_pixels().enumerate() {
+            match kind {
+                PixelKind::Color => {
+                    packed.mask[bit / 8] |= 1 << (bit % 8);
+                    packed.indices.push(index);
+                }
+                PixelKind::Remap => {
+                    packed.mask[bit / 8] |= 1 << (bit % 8);
+                    packed.player_colors[bit / 8] |= 1 << (bit % 8);
+                    packed.indices.push(index);
+                }
+                PixelKind::Skip | PixelKind::Shadow | PixelKind::Outline |
+                PixelKind::BlackOutline => {}
+            }
+        }
+        packed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slp::{NO_PLAYER, SlpLogicalShape};
+    use test_util::set_pixel_kinds;
+
+    #[test]
+    fn test_to_packed_and_blit() {
+        use slp::PixelKind::*;
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 3;
+        shape.header.height = 3;
+        shape.pixels = vec![0, 5, 0, 0, 0, 6, 7, 0, 0];
+        set_pixel_kinds(&mut shape, &[Skip, Color, Shadow, Skip, Skip, Remap, Color, Skip, Skip]);
+
+        let packed = shape.to_packed();
+        assert_eq!(vec![0b0110_0010, 0b0000_0000], packed.mask);
+        assert_eq!(vec![0b0010_0000, 0b0000_0000], packed.player_colors);
+        assert_eq!(vec![5, 6, 7], packed.indices);
+        assert!(packed.is_drawn(1, 0));
+        assert!(!packed.is_drawn(2, 0));
+        assert!(!packed.is_drawn(3, 0));
+        assert!(packed.is_player_color(2, 1));
+        assert!(!packed.is_player_color(1, 0));
+
+        let mut dst = vec![0xFFu8; 9];
+        packed.blit(&mut dst, 3, 0, 0, NO_PLAYER);
+        assert_eq!(vec![0xFF, 5, 0xFF, 0xFF, 0xFF, 6, 7, 0xFF, 0xFF], dst);
+
+        // Player colors are moved into the player's block
+        let mut dst = vec![0xFFu8; 9];
+        packed.blit(&mut dst, 3, 0, 0, 2);
+        assert_eq!(vec![0xFF, 5, 0xFF, 0xFF, 0xFF, 32 + 6, 7, 0xFF, 0xFF], dst);
+
+        // Clipped against the right and bottom edges
+        let mut dst = vec![0u8; 4];
+        packed.blit(&mut dst, 2, 1, 0, NO_PLAYER);
+        assert_eq!(vec![0, 0, 0, 0], dst);
+        let mut dst = vec![0u8; 4];
+        packed.blit(&mut dst, 2, 0, 1, NO_PLAYER);
+        assert_eq!(vec![0, 0, 0, 5], dst);
+
+        // Positions that would overflow are clipped too
+        let mut dst = vec![0u8; 4];
+        packed.blit(&mut dst, 2, u32::MAX, u32::MAX, NO_PLAYER);
+        assert_eq!(vec![0, 0, 0, 0], dst);
+    }
+}
@@ -0,0 +1,133 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! PNG export, enabled by the `png` feature.
+
+use error::*;
+use png;
+use rgba::{Palette, PixelFormat};
+use slp::{SlpFile, SlpLogicalShape};
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+impl SlpLogicalShape {
+    /// Writes the shape to `out` as an RGBA PNG, as drawn by
+    /// [to_rgba](struct.SlpLogicalShape.html#method.to_rgba) with straight alpha.
+    ///
+    /// `palette` must hold 256 colors. Skipped pixels are fully transparent.
+    pub fn write_png<W: Write>(&self,
+                               out: W,
+                               palette: &[(u8, u8, u8)],
+                               player_index: u8)
+                               -> Result<()> {
+        let palette = try!(Palette::from_tuples(palette));
+        self.write_png_with(out, &palette, player_index)
+    }
+
+    fn write_png_with<W: Write>(&self, out: W, palette: &Palette, player_index: u8) -> Result<()> {
+        let rgba = self.to_rgba(palette, player_index, PixelFormat::Rgba);
+        let mut encoder = png::Encoder::new(out, self.header.width, self.header.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = try!(encoder.write_header().map_err(io::Error::from));
+        try!(writer.write_image_data(&rgba).map_err(io::Error::from));
+        Ok(())
+    }
+}
+
+impl SlpFile {
+    /// Writes every shape to `dir` as `frame_000.png`, `frame_001.png` and so on, as by
+    /// [write_png](struct.SlpLogicalShape.html#method.write_png).
+    ///
+    /// `dir` must already exist; files already in it are overwritten.
+    pub fn write_png_frames<P: AsRef<Path>>(&self,
+                                            dir: P,
+                                            palette: &[(u8, u8, u8)],
+                                            player_index: u8)
+                                            -> Result<()> {
+        let palette = try!(Palette::from_tuples(palette));
+        for (index, shape) in self.shapes.iter().enumerate() {
+            let path = dir.as_ref().join(format!("frame_{:03}.png", index));
+            let file = try!(File::create(path));
+            try!(shape.write_png_with(io::BufWriter::new(file), &palette, player_index));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slp::{DrawCommand, SlpFile, SlpLogicalShape};
+    use std::env;
+    use std::fs;
+
+    const PNG_SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+
+    fn test_shape(width: u32) -> SlpLogicalShape {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = width;
+        shape.header.height = 1;
+        shape.pixels = vec![1; width as usize];
+        shape.commands = vec![vec![DrawCommand::Skip { count: 1 },
+                                   DrawCommand::Color { count: width - 1 }]];
+        shape
+    }
+
+    fn test_palette() -> Vec<(u8, u8, u8)> {
+        (0..256).map(|i| (i as u8, 0, 0)).collect()
+    }
+
+    /// Returns the width and height from the PNG's header chunk.
+    fn png_size(png: &[u8]) -> (u32, u32) {
+        let be_u32 = |bytes: &[u8]| bytes.iter().fold(0, |n, &b| n << 8 | b as u32);
+        (be_u32(&png[16..20]), be_u32(&png[20..24]))
+    }
+
+    #[test]
+    fn test_slp_logical_shape_write_png() {
+        let mut png = Vec::new();
+        test_shape(3).write_png(&mut png, &test_palette(), 1).unwrap();
+        assert_eq!(PNG_SIGNATURE, &png[..8]);
+        assert_eq!((3, 1), png_size(&png));
+
+        assert!(test_shape(3).write_png(&mut Vec::new(), &test_palette()[..255], 1).is_err());
+    }
+
+    #[test]
+    fn test_slp_file_write_png_frames() {
+        let dir = env::temp_dir().join("chariot_slp_test_write_png_frames");
+        fs::create_dir_all(&dir).unwrap();
+        let mut slp_file = SlpFile::new();
+        slp_file.shapes.push(test_shape(2));
+        slp_file.shapes.push(test_shape(4));
+        slp_file.write_png_frames(&dir, &test_palette(), 1).unwrap();
+
+        let png = fs::read(dir.join("frame_001.png")).unwrap();
+        assert_eq!(PNG_SIGNATURE, &png[..8]);
+        assert_eq!((4, 1), png_size(&png));
+        assert!(dir.join("frame_000.png").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
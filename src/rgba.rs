@@ -0,0 +1,432 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use error::*;
+use std::cmp;
+use std::ops::Deref;
+use slp::{PixelKind, SlpLogicalShape};
+
+/// Alpha used for shadow pixels, which are drawn as translucent black.
+pub const SHADOW_ALPHA: u8 = 0x80;
+
+/// The byte layout produced by [SlpLogicalShape::to_rgba](struct.SlpLogicalShape.html#method.to_rgba).
+///
+/// Both formats are 4 bytes per pixel in R, G, B, A order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// Straight (non-premultiplied) alpha.
+    Rgba,
+
+    /// Color channels are already multiplied by alpha.
+    ///
+    /// Upload this with a premultiplied blend mode (`ONE, ONE_MINUS_SRC_ALPHA`);
+    /// don't premultiply it again.
+    PremultipliedRgba,
+}
+
+/// 256 RGB colors, as taken by [to_rgba](struct.SlpLogicalShape.html#method.to_rgba) and
+/// friends.
+///
+/// A `&Palette` can be passed anywhere a `&[[u8; 3]; 256]` is expected.
+#[derive(Clone)]
+pub struct Palette {
+    colors: [[u8; 3]; 256],
+}
+
+impl Palette {
+    /// Builds a palette from 768 bytes of packed RGB, as palettes are usually stored on disk.
+    pub fn from_rgb(bytes: &[u8]) -> Result<Palette> {
+        if bytes.len() != 256 * 3 {
+            return Err(ErrorKind::BadPaletteLength(bytes.len()).into());
+        }
+        let mut colors = [[0u8; 3]; 256];
+        for (color, rgb) in colors.iter_mut().zip(bytes.chunks(3)) {
+            color.copy_from_slice(rgb);
+        }
+        Ok(Palette { colors: colors })
+    }
+
+    /// Builds a palette from 256 `(r, g, b)` tuples.
+    pub fn from_tuples(tuples: &[(u8, u8, u8)]) -> Result<Palette> {
+        if tuples.len() != 256 {
+            return Err(ErrorKind::BadPaletteLength(tuples.len() * 3).into());
+        }
+        let mut colors = [[0u8; 3]; 256];
+        for (color, &(r, g, b)) in colors.iter_mut().zip(tuples.iter()) {
+            *color = [r, g, b];
+        }
+        Ok(Palette { colors: colors })
+    }
+}
+
+impl From<[[u8; 3]; 256]> for Palette {
+    fn from(colors: [[u8; 3]; 256]) -> Palette {
+        Palette { colors: colors }
+    }
+}
+
+impl Deref for Palette {
+    type Target = [[u8; 3]; 256];
+
+    fn deref(&self) -> &[[u8; 3]; 256] {
+        &self.colors
+    }
+}
+
+impl SlpLogicalShape {
+    /// Resolves the shape's palette indices into a tightly packed `width * height * 4` buffer.
+    ///
+    /// Player color pixels are looked up in `player_index`'s block of 16 palette entries,
+    /// regardless of the player the shape was decoded for, and player color outlines use the
+    /// first entry of that block. Skipped pixels are fully transparent and shadow pixels are
+    /// black with [SHADOW_ALPHA](constant.SHADOW_ALPHA.html).
+    pub fn to_rgba(&self,
+                   palette: &[[u8; 3]; 256],
+                   player_index: u8,
+                   format: PixelFormat)
+                   -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(self.pixels.len() * 4);
+        for (kind, index) in self.drawn_pixels() {
+            rgba.extend_from_slice(&rgba_pixel(kind, index, palette, player_index, format));
+        }
+        rgba
+    }
+
+    /// Splits the shape into a plane of palette indices and a plane saying how each pixel is
+    /// drawn, so a shader can do the palette lookup and player tinting itself.
+    ///
+    /// The mask holds 0 for skipped pixels, 1 for colors, 2 for player colors, 3 for shadows,
+    /// 4 for player color outlines and 5 for black outlines. Player color indices are relative
+    /// (0-15) whatever player the shape was decoded for, and all other pixels without a color
+    /// have an index of 0.
+    pub fn to_index_and_mask(&self) -> (Vec<u8>, Vec<u8>) {
+        self.drawn_pixels()
+            .map(|(kind, index)| match kind {
+                PixelKind::Skip => (0, 0),
+                PixelKind::Color => (index, 1),
+                PixelKind::Remap => (index, 2),
+                PixelKind::Shadow => (0, 3),
+                PixelKind::Outline => (0, 4),
+                PixelKind::BlackOutline => (0, 5),
+            })
+            .unzip()
+    }
+
+    /// Writes the shape as straight RGBA into the start of `dst`, where each row begins
+    /// `stride` bytes after the previous one.
+    ///
+    /// Every pixel of the shape's rectangle is written, including transparent ones.
+    pub fn blit_rgba(&self,
+                     dst: &mut [u8],
+                     stride: usize,
+                     palette: &[[u8; 3]; 256],
+                     player_index: u8)
+                     -> Result<()> {
+        let width = self.header.width as usize;
+        let height = self.header.height as usize;
+        if width == 0 || height == 0 {
+            return Ok(());
+        }
+
+        let needed = (height - 1) * stride + width * 4;
+        if stride < width * 4 || dst.len() < needed {
+            return Err(ErrorKind::DestinationTooSmall(needed, dst.len()).into());
+        }
+
+        for (loc, (kind, index)) in self.drawn_pixels().enumerate() {
+            let (x, y) = (loc % width, loc / width);
+            let offset = y * stride + x * 4;
+            let pixel = rgba_pixel(kind, index, palette, player_index, PixelFormat::Rgba);
+            dst[offset..offset + 4].copy_from_slice(&pixel);
+        }
+        Ok(())
+    }
+
+    /// Draws the shape as straight RGBA with its top left corner at `(x, y)` of a larger
+    /// image in `dst`, where each row begins `stride_bytes` after the previous one.
+    ///
+    /// Pixels that fall outside the image are clipped, and skipped pixels leave `dst` as it was.
+    /// Player colors are drawn in `player_index`'s colors, as with [to_rgba](#method.to_rgba).
+    pub fn blit_rgba_strided(&self,
+                             dst: &mut [u8],
+                             stride_bytes: usize,
+                             x: u32,
+                             y: u32,
+                             palette: &[[u8; 3]; 256],
+                             player_index: u8) {
+        let width = self.header.width as usize;
+        let dst_width = stride_bytes / 4;
+        let (x, y) = (x as usize, y as usize);
+        for (loc, (kind, index)) in self.drawn_pixels().enumerate() {
+            let (dst_x, dst_y) = (x + loc % width, y + loc / width);
+            let offset = dst_y * stride_bytes + dst_x * 4;
+            if kind == PixelKind::Skip || dst_x >= dst_width || offset + 4 > dst.len() {
+                continue;
+            }
+            let pixel = rgba_pixel(kind, index, palette, player_index, PixelFormat::Rgba);
+            dst[offset..offset + 4].copy_from_slice(&pixel);
+        }
+    }
+
+    /// Renders a straight RGBA preview of the shape whose larger dimension is at most `max_dim`,
+    /// returning it along with its width and height.
+    ///
+    /// The whole canvas is scaled with nearest-neighbor sampling, so the aspect ratio and any
+    /// transparent margins are kept. Shapes that already fit aren't scaled up. Player colors are
+    /// drawn in `player_index`'s colors, as with [to_rgba](#method.to_rgba).
+    pub fn thumbnail(&self,
+                     palette: &[[u8; 3]; 256],
+                     player_index: u8,
+                     max_dim: u32)
+                     -> (Vec<u8>, u32, u32) {
+        let (width, height) = (self.header.width, self.header.height);
+        let larger = cmp::max(width, height);
+        if larger == 0 || max_dim == 0 {
+            return (Vec::new(), 0, 0);
+        }
+
+        let (thumb_width, thumb_height) = if larger <= max_dim {
+            (width, height)
+        } else {
+            (cmp::max(1, (width as u64 * max_dim as u64 / larger as u64) as u32),
+             cmp::max(1, (height as u64 * max_dim as u64 / larger as u64) as u32))
+        };
+
+        let pixels: Vec<(PixelKind, u8)> = self.drawn_pixels().collect();
+        let mut rgba = Vec::with_capacity((thumb_width * thumb_height * 4) as usize);
+        for ty in 0..thumb_height {
+            // Sample the source pixel under the center of each thumbnail pixel
+            let y = ((2 * ty + 1) as u64 * height as u64 / (2 * thumb_height) as u64) as u32;
+            for tx in 0..thumb_width {
+                let x = ((2 * tx + 1) as u64 * width as u64 / (2 * thumb_width) as u64) as u32;
+                let (kind, index) = pixels[(y * width + x) as usize];
+                rgba.extend_from_slice(&rgba_pixel(kind,
+                                                   index,
+                                                   palette,
+                                                   player_index,
+                                                   PixelFormat::Rgba));
+            }
+        }
+        (rgba, thumb_width, thumb_height)
+    }
+}
+
+/// Resolves a pixel of the given kind, where `index` is relative for player colors.
+fn rgba_pixel(kind: PixelKind,
+              index: u8,
+              palette: &[[u8; 3]; 256],
+              player_index: u8,
+              format: PixelFormat)
+              -> [u8; 4] {
+    let (color, alpha) = match kind {
+        PixelKind::Skip => ([0u8; 3], 0u8),
+        PixelKind::Color => (palette[index as usize], 0xFF),
+        PixelKind::Remap => (palette[player_color(player_index, index) as usize], 0xFF),
+        PixelKind::Shadow => ([0u8; 3], SHADOW_ALPHA),
+        PixelKind::Outline => (palette[player_color(player_index, 0) as usize], 0xFF),
+        PixelKind::BlackOutline => ([0u8; 3], 0xFF),
+    };
+    match format {
+        PixelFormat::Rgba => [color[0], color[1], color[2], alpha],
+        PixelFormat::PremultipliedRgba => {
+            [premultiply(color[0], alpha),
+             premultiply(color[1], alpha),
+             premultiply(color[2], alpha),
+             alpha]
+        }
+    }
+}
+
+/// Maps a relative player color index into `player_index`'s color block.
+fn player_color(player_index: u8, index: u8) -> u8 {
+    player_index.wrapping_mul(16).wrapping_add(index)
+}
+
+fn premultiply(channel: u8, alpha: u8) -> u8 {
+    ((channel as u32 * alpha as u32 + 127) / 255) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Palette, PixelFormat, SHADOW_ALPHA, premultiply};
+    use slp::{DrawCommand, PixelKind, SlpLogicalShape};
+    use test_util::set_pixel_kinds;
+
+    fn test_shape() -> SlpLogicalShape {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 3;
+        shape.header.height = 1;
+        shape.pixels = vec![0, 1, 2];
+        set_pixel_kinds(&mut shape, &[PixelKind::Skip, PixelKind::Color, PixelKind::Shadow]);
+        shape
+    }
+
+    fn test_palette() -> [[u8; 3]; 256] {
+        let mut palette = [[0u8; 3]; 256];
+        palette[1] = [200, 100, 50];
+        palette[2] = [255, 255, 255];
+        palette
+    }
+
+    #[test]
+    fn test_to_rgba() {
+        let rgba = test_shape().to_rgba(&test_palette(), 1, PixelFormat::Rgba);
+        assert_eq!(vec![0, 0, 0, 0, 200, 100, 50, 255, 0, 0, 0, SHADOW_ALPHA], rgba);
+    }
+
+    #[test]
+    fn test_to_rgba_premultiplied() {
+        let mut shape = test_shape();
+        let opaque = shape.to_rgba(&test_palette(), 1, PixelFormat::Rgba);
+        let premultiplied = shape.to_rgba(&test_palette(), 1, PixelFormat::PremultipliedRgba);
+        assert_eq!(opaque, premultiplied);
+
+        // Only translucent pixels are affected
+        shape.pixels[0] = 2;
+        shape.commands[0][0] = DrawCommand::Color { count: 1 };
+        let rgba = shape.to_rgba(&test_palette(), 1, PixelFormat::PremultipliedRgba);
+        assert_eq!(&[255, 255, 255, 255], &rgba[..4]);
+        assert_eq!(128, premultiply(255, SHADOW_ALPHA));
+        assert_eq!(0, premultiply(255, 0));
+        assert_eq!(100, premultiply(100, 255));
+    }
+
+    #[test]
+    fn test_to_rgba_remap_uses_player_block() {
+        let mut palette = test_palette();
+        palette[16 + 3] = [1, 1, 1];
+        palette[32 + 3] = [2, 2, 2];
+
+        let mut shape = test_shape();
+        // Decoded for player 1, though only the relative index is used
+        shape.pixels[0] = 16 + 3;
+        shape.commands[0][0] = DrawCommand::PlayerColor { relative: vec![3] };
+        assert_eq!(&[1, 1, 1, 255], &shape.to_rgba(&palette, 1, PixelFormat::Rgba)[..4]);
+        assert_eq!(&[2, 2, 2, 255], &shape.to_rgba(&palette, 2, PixelFormat::Rgba)[..4]);
+    }
+
+    #[test]
+    fn test_blit_rgba() {
+        let shape = test_shape();
+        let mut dst = vec![9u8; 16];
+        shape.blit_rgba(&mut dst, 16, &test_palette(), 1).unwrap();
+        assert_eq!(shape.to_rgba(&test_palette(), 1, PixelFormat::Rgba), &dst[..12]);
+        assert_eq!(&[9, 9, 9, 9], &dst[12..]);
+
+        assert!(shape.blit_rgba(&mut dst[..11], 12, &test_palette(), 1).is_err());
+        assert!(shape.blit_rgba(&mut dst, 8, &test_palette(), 1).is_err());
+    }
+
+    #[test]
+    fn test_blit_rgba_strided() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 2;
+        shape.header.height = 2;
+        shape.pixels = vec![1, 0, 1, 1];
+        set_pixel_kinds(&mut shape,
+                        &[PixelKind::Color, PixelKind::Skip, PixelKind::Color, PixelKind::Shadow]);
+
+        // A 3x2 image, with a 4 byte gap after each row
+        let mut dst = vec![9u8; 32];
+        shape.blit_rgba_strided(&mut dst, 16, 1, 0, &test_palette(), 1);
+        assert_eq!(vec![9, 9, 9, 9, 200, 100, 50, 255, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 200,
+                        100, 50, 255, 0, 0, 0, SHADOW_ALPHA, 9, 9, 9, 9],
+                   dst);
+
+        // Only the top left pixel lands inside the image
+        let mut dst = vec![9u8; 32];
+        shape.blit_rgba_strided(&mut dst, 16, 2, 1, &test_palette(), 1);
+        assert_eq!(&[200, 100, 50, 255], &dst[24..28]);
+        assert!(dst[..24].iter().chain(dst[28..].iter()).all(|&b| b == 9));
+
+        // Player colors use the given player's block
+        let mut palette = test_palette();
+        palette[32 + 3] = [2, 2, 2];
+        shape.pixels[0] = 3;
+        shape.commands[0][0] = DrawCommand::PlayerColor { relative: vec![3] };
+        let mut dst = vec![9u8; 32];
+        shape.blit_rgba_strided(&mut dst, 16, 0, 0, &palette, 2);
+        assert_eq!(&[2, 2, 2, 255], &dst[..4]);
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 8;
+        shape.header.height = 2;
+        shape.pixels = vec![1; 16];
+        // Leave the right half transparent
+        let row = vec![DrawCommand::Color { count: 4 }, DrawCommand::Skip { count: 4 }];
+        shape.commands = vec![row.clone(), row];
+
+        let (rgba, width, height) = shape.thumbnail(&test_palette(), 1, 4);
+        assert_eq!((4, 1), (width, height));
+        assert_eq!(vec![200, 100, 50, 255, 200, 100, 50, 255, 0, 0, 0, 0, 0, 0, 0, 0], rgba);
+
+        // Already small enough
+        let (rgba, width, height) = shape.thumbnail(&test_palette(), 1, 8);
+        assert_eq!((8, 2), (width, height));
+        assert_eq!(shape.to_rgba(&test_palette(), 1, PixelFormat::Rgba), rgba);
+
+        assert_eq!((Vec::new(), 0, 0), shape.thumbnail(&test_palette(), 1, 0));
+
+        // Player colors use the given player's block
+        let mut palette = test_palette();
+        palette[32 + 3] = [2, 2, 2];
+        shape.pixels[0] = 3;
+        shape.commands[0] = vec![DrawCommand::PlayerColor { relative: vec![3] },
+                                 DrawCommand::Color { count: 3 },
+                                 DrawCommand::Skip { count: 4 }];
+        let (rgba, _, _) = shape.thumbnail(&palette, 2, 8);
+        assert_eq!(&[2, 2, 2, 255], &rgba[..4]);
+    }
+
+    #[test]
+    fn test_palette_from_rgb() {
+        let bytes: Vec<u8> = (0..768).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::from_rgb(&bytes).unwrap();
+        assert_eq!([5, 5, 5], palette[5]);
+
+        let shape = test_shape();
+        assert_eq!(&[1, 1, 1, 255], &shape.to_rgba(&palette, 1, PixelFormat::Rgba)[4..8]);
+
+        assert!(Palette::from_rgb(&bytes[..767]).is_err());
+
+        let tuples: Vec<(u8, u8, u8)> = (0..256).map(|i| (i as u8, 0, 255 - i as u8)).collect();
+        let palette = Palette::from_tuples(&tuples).unwrap();
+        assert_eq!([5, 0, 250], palette[5]);
+        assert!(Palette::from_tuples(&tuples[..255]).is_err());
+        assert_eq!(test_palette(), *Palette::from(test_palette()));
+    }
+
+    #[test]
+    fn test_to_index_and_mask() {
+        let mut shape = test_shape();
+        shape.header.width = 4;
+        shape.pixels.push(32 + 5);
+        shape.commands[0].push(DrawCommand::PlayerColor { relative: vec![5] });
+        let (indices, mask) = shape.to_index_and_mask();
+        assert_eq!(vec![0, 1, 0, 5], indices);
+        assert_eq!(vec![0, 1, 3, 2], mask);
+    }
+}
@@ -0,0 +1,128 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use slp::{DrawCommand, SlpLogicalShape};
+
+/// What a run passed to [for_each_run](struct.SlpLogicalShape.html#method.for_each_run) draws.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunKind {
+    Skip,
+    Color,
+    /// Player colors, as indices relative to a player's block of 16 palette entries.
+    Remap,
+    /// A single palette index, drawn by a fill command.
+    Fill,
+    Shadow,
+    Outline,
+    BlackOutline,
+}
+
+impl SlpLogicalShape {
+    /// Calls `f` with the kind, row, starting x and indices of every run in `commands`, from
+    /// the top left to the bottom right.
+    ///
+    /// The slice has an entry for every pixel of the run: the palette indices of `Color` and
+    /// `Fill` runs, as found in `pixels`, the relative indices of `Remap` runs and zeros for
+    /// everything else. Nothing is allocated per run.
+    pub fn for_each_run<F: FnMut(RunKind, u32, u32, &[u8])>(&self, mut f: F) {
+        let width = self.header.width as usize;
+        let mut zeros = Vec::new();
+        for (y, row) in self.commands.iter().enumerate() {
+            let mut x = 0;
+            for command in row {
+                let count = command.len() as usize;
+                let kind = match *command {
+                    DrawCommand::Skip { .. } => RunKind::Skip,
+                    DrawCommand::Color { .. } => RunKind::Color,
+                    DrawCommand::Fill { .. } => RunKind::Fill,
+                    DrawCommand::PlayerColor { .. } => RunKind::Remap,
+                    DrawCommand::Shadow { .. } => RunKind::Shadow,
+                    DrawCommand::Outline { .. } => RunKind::Outline,
+                    DrawCommand::BlackOutline { .. } => RunKind::BlackOutline,
+                };
+                let indices: &[u8] = match *command {
+                    DrawCommand::Color { .. } |
+                    DrawCommand::Fill { .. } => {
+                        let start = y * width + x as usize;
+                        &self.pixels[start..start + count]
+                    }
+                    DrawCommand::PlayerColor { ref relative } => relative,
+                    _ => {
+                        if zeros.len() < count {
+                            zeros.resize(count, 0);
+                        }
+                        &zeros[..count]
+                    }
+                };
+                f(kind, y as u32, x, indices);
+                x += count as u32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RunKind;
+    use slp::{DrawCommand, SlpLogicalShape};
+
+    #[test]
+    fn test_for_each_run() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 4;
+        shape.header.height = 2;
+        shape.pixels = vec![9, 9, 3, 0, 9, 9, 9, 0];
+        shape.commands = vec![vec![DrawCommand::Color { count: 2 },
+                                   DrawCommand::PlayerColor { relative: vec![3] },
+                                   DrawCommand::Shadow { count: 1 }],
+                              vec![DrawCommand::Fill { count: 3 },
+                                   DrawCommand::Skip { count: 1 }]];
+
+        let mut runs = Vec::new();
+        shape.for_each_run(|kind, y, x, indices| runs.push((kind, y, x, indices.to_vec())));
+        assert_eq!(vec![(RunKind::Color, 0, 0, vec![9, 9]),
+                        (RunKind::Remap, 0, 2, vec![3]),
+                        (RunKind::Shadow, 0, 3, vec![0]),
+                        (RunKind::Fill, 1, 0, vec![9, 9, 9]),
+                        (RunKind::Skip, 1, 3, vec![0])],
+                   runs);
+    }
+
+    #[test]
+    fn test_for_each_run_after_edits() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 3;
+        shape.header.height = 1;
+        shape.pixels = vec![9, 9, 3];
+        shape.commands = vec![vec![DrawCommand::Color { count: 2 },
+                                   DrawCommand::PlayerColor { relative: vec![3] }]];
+
+        // Colors come from the current pixels, and player colors stay relative when recolored
+        shape.pixels[1] = 4;
+        shape.recolor(2);
+        let mut runs = Vec::new();
+        shape.for_each_run(|kind, _, _, indices| runs.push((kind, indices.to_vec())));
+        assert_eq!(vec![(RunKind::Color, vec![9, 4]), (RunKind::Remap, vec![3])], runs);
+        assert_eq!(vec![9, 4, 32 + 3], shape.pixels);
+    }
+}
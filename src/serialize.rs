@@ -0,0 +1,116 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+//! Serde support for the header types, enabled by the `serde` feature.
+
+use error::*;
+use slp::{SlpExtendedHeader, SlpHeader};
+
+use std::convert::TryFrom;
+
+/// What an [SlpHeader](../slp/struct.SlpHeader.html) is serialized as, with its byte arrays
+/// as text.
+///
+/// The comment is the trimmed [comment_str](../slp/struct.SlpHeader.html#method.comment_str),
+/// so a version 4 header's `comment` bytes come back zeroed; its fields are kept in
+/// `extended` instead.
+#[derive(Serialize, Deserialize)]
+pub struct SlpHeaderText {
+    file_version: String,
+    shape_count: u32,
+    comment: String,
+    extended: Option<SlpExtendedHeader>,
+}
+
+impl From<SlpHeader> for SlpHeaderText {
+    fn from(header: SlpHeader) -> SlpHeaderText {
+        SlpHeaderText {
+            file_version: header.version_str().into_owned(),
+            shape_count: header.shape_count,
+            comment: header.comment_str(),
+            extended: header.extended,
+        }
+    }
+}
+
+impl TryFrom<SlpHeaderText> for SlpHeader {
+    type Error = Error;
+
+    fn try_from(text: SlpHeaderText) -> Result<SlpHeader> {
+        let mut header = try!(SlpHeader::with_version_and_comment(&text.file_version,
+                                                                  &text.comment));
+        header.shape_count = text.shape_count;
+        header.extended = text.extended;
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+    use slp::{SlpHeader, SlpShapeHeader};
+
+    #[test]
+    fn test_slp_header_serialize() {
+        let mut header = SlpHeader::with_version_and_comment("2.0N", "ArtDesk 1.00 SLP Writer")
+            .unwrap();
+        header.shape_count = 3;
+        let json = serde_json::to_string(&header).unwrap();
+        assert_eq!(concat!(r#"{"file_version":"2.0N","shape_count":3,"#,
+                           r#""comment":"ArtDesk 1.00 SLP Writer","extended":null}"#),
+                   json);
+
+        let header: SlpHeader = serde_json::from_str(&json).unwrap();
+        assert_eq!(b"2.0N", &header.file_version);
+        assert_eq!(3, header.shape_count);
+        assert_eq!("ArtDesk 1.00 SLP Writer", header.comment_str());
+    }
+
+    #[test]
+    fn test_slp_header_serialize_latin1_comment() {
+        let mut header = SlpHeader::with_version_and_comment("2.0N", "").unwrap();
+        header.comment = [0xE9; 24];
+        let json = serde_json::to_string(&header).unwrap();
+        let reread: SlpHeader = serde_json::from_str(&json).unwrap();
+        assert_eq!([0xE9; 24], reread.comment);
+
+        let json = r#"{"file_version":"2.0N","shape_count":0,"comment":"\u0100","extended":null}"#;
+        assert!(serde_json::from_str::<SlpHeader>(json).is_err());
+    }
+
+    #[test]
+    fn test_slp_header_deserialize_bad_version() {
+        let json = r#"{"file_version":"2.0NN","shape_count":0,"comment":"","extended":null}"#;
+        assert!(serde_json::from_str::<SlpHeader>(json).is_err());
+    }
+
+    #[test]
+    fn test_slp_shape_header_serialize() {
+        let mut header = SlpShapeHeader::new();
+        header.width = 2;
+        header.center_x = -1;
+        let json = serde_json::to_string(&header).unwrap();
+        let header: SlpShapeHeader = serde_json::from_str(&json).unwrap();
+        assert_eq!(2, header.width);
+        assert_eq!(-1, header.center_x);
+    }
+}
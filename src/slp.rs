@@ -21,24 +21,61 @@
 // SOFTWARE.
 //
 
+use encode::{SHAPE_HEADER_SIZE, SLP_HEADER_SIZE};
 use error::*;
+use options::{DecodeOptions, PixelLayout};
 
 use chariot_io_tools::ReadExt;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io;
 use std::io::SeekFrom;
 use std::io::prelude::*;
+use std::iter;
 use std::mem::size_of;
+use std::ops::Range;
 
 use std::path::Path;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 /// A struct containing SLP metadata.
 ///
 /// A single SlpHeader must exist at the beginning of an [SlpFile](struct.SlpFile.html).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde",
+           serde(into = "::serialize::SlpHeaderText", try_from = "::serialize::SlpHeaderText"))]
 pub struct SlpHeader {
-    /// This should always be `2.0N`
+    /// `2.0N`, or `4.0X` and the like for the newer format
     pub file_version: [u8; 4],
     pub shape_count: u32,
     pub comment: [u8; 24],
+
+    /// The fields that version 4 files keep in place of the comment.
+    ///
+    /// Only set for version 4 files; `shape_count` and `comment` still hold the raw bytes.
+    pub extended: Option<SlpExtendedHeader>,
+}
+
+/// The additional header fields of a version 4 SLP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SlpExtendedHeader {
+    pub frame_type: u16,
+    pub num_directions: u16,
+    pub frames_per_direction: u16,
+    pub palette_id: u32,
+
+    /// Where the table of shape headers starts.
+    pub offset_main: u32,
+
+    /// Where the table of secondary (e.g. shadow) shape headers starts, or 0 if there is none.
+    pub offset_secondary: u32,
 }
 
 impl SlpHeader {
@@ -47,10 +84,52 @@ impl SlpHeader {
             file_version: [0u8; 4],
             shape_count: 0u32,
             comment: [0u8; 24],
+            extended: None,
         }
     }
 
-    // TODO: Implement writing
+    /// Creates a header with no shapes, the given version and comment.
+    ///
+    /// `version` must be of the form `<digit>.<digit><suffix>` and fit in `file_version`, and
+    /// `comment` must fit in the part of `comment` that the version uses for text. The comment
+    /// is stored as Latin-1, the way [comment_str](#method.comment_str) reads it, so it can't
+    /// contain characters past U+00FF.
+    pub fn with_version_and_comment(version: &str, comment: &str) -> Result<SlpHeader> {
+        let mut header = SlpHeader::new();
+        if version.len() > header.file_version.len() {
+            return Err(ErrorKind::InvalidHeader(format!("version {:?} is longer than {} bytes",
+                                                        version,
+                                                        header.file_version.len()))
+                .into());
+        }
+        header.file_version[..version.len()].copy_from_slice(version.as_bytes());
+        let comment_length = match header.version() {
+            Some(version) => version.comment_length(),
+            None => {
+                return Err(ErrorKind::InvalidHeader(format!("malformed version {:?}", version))
+                    .into())
+            }
+        };
+        let mut latin1 = Vec::with_capacity(comment.len());
+        for c in comment.chars() {
+            if c as u32 > 0xFF {
+                return Err(ErrorKind::InvalidHeader(format!("comment {:?} has {:?}, which isn't \
+                                                             Latin-1",
+                                                            comment,
+                                                            c))
+                    .into());
+            }
+            latin1.push(c as u8);
+        }
+        if latin1.len() > comment_length {
+            return Err(ErrorKind::InvalidHeader(format!("comment {:?} is longer than {} bytes",
+                                                        comment,
+                                                        comment_length))
+                .into());
+        }
+        header.comment[..latin1.len()].copy_from_slice(&latin1);
+        Ok(header)
+    }
 
     pub fn read_from<S: Read>(stream: &mut S) -> Result<SlpHeader> {
         let mut header = SlpHeader::new();
@@ -58,14 +137,107 @@ impl SlpHeader {
         header.shape_count = try!(stream.read_u32());
         try!(stream.read_exact(&mut header.comment));
 
-        if header.file_version != "2.0N".as_bytes() {
+        if header.version().map(|v| v.major) == Some(4) {
+            // The frame count is only 16 bits, followed by the frame type
+            let bytes = &header.comment;
+            let u16_at = |i: usize| bytes[i] as u16 | (bytes[i + 1] as u16) << 8;
+            let u32_at = |i: usize| u16_at(i) as u32 | (u16_at(i + 2) as u32) << 16;
+            header.extended = Some(SlpExtendedHeader {
+                frame_type: (header.shape_count >> 16) as u16,
+                num_directions: u16_at(0),
+                frames_per_direction: u16_at(2),
+                palette_id: u32_at(4),
+                offset_main: u32_at(8),
+                offset_secondary: u32_at(12),
+            });
+            header.shape_count &= 0xFFFF;
+        } else if header.file_version != "2.0N".as_bytes() {
             return Err(ErrorKind::InvalidSlp("bad header".into()).into());
         }
         Ok(header)
     }
+
+    /// Returns where the table of shape headers starts, which directly follows this header
+    /// except in version 4 files.
+    pub fn shape_table_offset(&self) -> u64 {
+        self.extended.map_or(SLP_HEADER_SIZE, |extended| extended.offset_main as u64)
+    }
+
+    /// Fails with `UnsupportedVersion` unless the shapes' pixel data can be decoded, which
+    /// isn't the case for version 4 files yet.
+    pub(crate) fn check_decodable(&self) -> Result<()> {
+        if self.extended.is_some() {
+            return Err(ErrorKind::UnsupportedVersion(self.version_str().into_owned()).into());
+        }
+        Ok(())
+    }
+
+    /// Returns `file_version` as text, with any trailing NUL bytes removed.
+    ///
+    /// Invalid UTF-8 is replaced rather than rejected, so this is always safe to display.
+    pub fn version_str(&self) -> Cow<'_, str> {
+        let end = self.file_version.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        String::from_utf8_lossy(&self.file_version[..end])
+    }
+
+    /// Returns the comment as text, up to the first NUL byte and without trailing spaces.
+    ///
+    /// The bytes are read as Latin-1, with control characters dropped. Only the part of
+    /// `comment` that the file's version uses for text is included, so this is empty for
+    /// versions without a comment.
+    pub fn comment_str(&self) -> String {
+        let length = self.version().map_or(self.comment.len(), |v| v.comment_length());
+        let comment = &self.comment[..length];
+        let end = comment.iter().position(|&b| b == 0).unwrap_or(length);
+        let text: String = comment[..end]
+            .iter()
+            .map(|&b| b as char)
+            .filter(|c| !c.is_control())
+            .collect();
+        text.trim_end_matches(' ').to_string()
+    }
+
+    /// Parses `file_version` into its components.
+    ///
+    /// Returns `None` if the version isn't of the form `<digit>.<digit><suffix>`.
+    pub fn version(&self) -> Option<SlpVersion> {
+        let v = &self.file_version;
+        if !v[0].is_ascii_digit() || v[1] != b'.' || !v[2].is_ascii_digit() {
+            return None;
+        }
+        Some(SlpVersion {
+            major: v[0] - b'0',
+            minor: v[2] - b'0',
+            suffix: v[3],
+        })
+    }
+}
+
+/// The structured form of an [SlpHeader](struct.SlpHeader.html)'s `file_version`.
+///
+/// `2.0N` parses as `major: 2, minor: 0, suffix: b'N'`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlpVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub suffix: u8,
+}
+
+impl SlpVersion {
+    /// The number of bytes at the start of `SlpHeader::comment` that are free-form text.
+    ///
+    /// Version 2 files have a 24 byte comment. Version 4 files use those bytes for
+    /// additional header fields instead, so they have no comment at all.
+    pub fn comment_length(&self) -> usize {
+        match self.major {
+            4 => 0,
+            _ => 24,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 /// A 32-byte struct containing frame metadata.
 ///
 /// One of these will exists for every frame in an [SlpFile](struct.SlpFile.html).
@@ -79,7 +251,13 @@ pub struct SlpShapeHeader {
 
     /// Pointer to an array of u16 pairs used to indicate padding.
     pub shape_outline_offset: u32,
+
+    /// Selects the palette the shape is meant to be drawn with; see
+    /// [default_palette_id_for](fn.default_palette_id_for.html).
     pub palette_offset: u32,
+
+    /// Flags describing how the shape is drawn; see [palette_id](#method.palette_id) and
+    /// [render_flags](#method.render_flags).
     pub properties: u32,
     pub width: u32,
     pub height: u32,
@@ -101,8 +279,6 @@ impl SlpShapeHeader {
         }
     }
 
-    // TODO: Implement writing
-
     fn read_from_file<R: Read + Seek>(file: &mut R) -> Result<SlpShapeHeader> {
         let mut header = SlpShapeHeader::new();
         header.shape_data_offsets = try!(file.read_u32());
@@ -115,13 +291,290 @@ impl SlpShapeHeader {
         header.center_y = try!(file.read_i32());
         Ok(header)
     }
+
+    /// Returns the low byte of `properties`, which selects which of the game's palettes the
+    /// shape was drawn for.
+    pub fn palette_id(&self) -> u8 {
+        self.properties as u8
+    }
+
+    /// Returns the second byte of `properties`, where per-shape rendering flags are kept.
+    ///
+    /// What the individual flags mean isn't known yet, so they're returned as they are.
+    pub fn render_flags(&self) -> u8 {
+        (self.properties >> 8) as u8
+    }
+
+    /// Returns `properties` as it was read, for flags not covered by the other accessors.
+    pub fn properties_raw(&self) -> u32 {
+        self.properties
+    }
+
+    /// Returns true if the shape has no pixels at all, in which case its tables aren't used.
+    pub(crate) fn is_zero_area(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// Fails if either of the shape's per-row tables would run past `len` bytes.
+    fn check_tables_within(&self, shape_index: usize, len: u64) -> Result<()> {
+        if self.is_zero_area() {
+            return Ok(());
+        }
+        let table_size = self.height as u64 * size_of::<u32>() as u64;
+        let tables = [("outline", self.shape_outline_offset), ("row offset", self.shape_data_offsets)];
+        for &(name, offset) in &tables {
+            if offset as u64 + table_size > len {
+                return Err(ErrorKind::InvalidSlp(format!("shape {}'s {} table at offset {} \
+                                                          extends past the end of the {} byte \
+                                                          file",
+                                                         shape_index,
+                                                         name,
+                                                         offset,
+                                                         len))
+                    .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resource id of the game's standard palette, stored as `50500.bin` in `Interfac.drs`.
+pub const DEFAULT_PALETTE_ID: u32 = 50500;
+
+/// Returns the id of the palette resource that a shape header's `palette_offset` refers to,
+/// or `None` if the value isn't one we know about.
+///
+/// Only 0, which every shape in the original game uses to mean the standard palette, is known
+/// so far. Callers should fall back to a palette of their choosing for anything else.
+pub fn default_palette_id_for(palette_offset: u32) -> Option<u32> {
+    match palette_offset {
+        0 => Some(DEFAULT_PALETTE_ID),
+        _ => None,
+    }
 }
 
 pub type SlpPixels = Vec<u8>;
 
+/// Describes how a single pixel of an [SlpLogicalShape](struct.SlpLogicalShape.html) was drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PixelKind {
+    /// Nothing was drawn; the pixel is transparent.
+    Skip,
+    /// The pixel is a regular palette index.
+    Color,
+    /// The pixel is a player color.
+    Remap,
+    /// The pixel darkens whatever is drawn underneath it.
+    Shadow,
+    /// The pixel is part of the player colored silhouette drawn where a unit is hidden behind
+    /// something, such as a building.
+    Outline,
+    /// Like `Outline`, but drawn in black rather than the player's color.
+    BlackOutline,
+}
+
+/// A run of pixels in a row of an [SlpLogicalShape](struct.SlpLogicalShape.html) that are all
+/// drawn the same way, along with what's needed to draw them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DrawCommand {
+    /// Nothing is drawn; the pixels are transparent.
+    Skip { count: u32 },
+    /// Regular palette indices, which are kept in the shape's `pixels`.
+    Color { count: u32 },
+    /// Like `Color`, but drawn by a fill command, so its pixels all had the same index when it
+    /// was read.
+    Fill { count: u32 },
+    /// Player color indices relative to a player's block of 16 palette entries, one per pixel.
+    PlayerColor { relative: Vec<u8> },
+    Shadow { count: u32 },
+    Outline { count: u32 },
+    BlackOutline { count: u32 },
+}
+
+impl DrawCommand {
+    /// Returns how many pixels the command covers.
+    pub fn len(&self) -> u32 {
+        match *self {
+            DrawCommand::PlayerColor { ref relative } => relative.len() as u32,
+            DrawCommand::Skip { count } |
+            DrawCommand::Color { count } |
+            DrawCommand::Fill { count } |
+            DrawCommand::Shadow { count } |
+            DrawCommand::Outline { count } |
+            DrawCommand::BlackOutline { count } => count,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns how each of the pixels the command covers is drawn.
+    pub fn kind(&self) -> PixelKind {
+        match *self {
+            DrawCommand::Skip { .. } => PixelKind::Skip,
+            DrawCommand::Color { .. } | DrawCommand::Fill { .. } => PixelKind::Color,
+            DrawCommand::PlayerColor { .. } => PixelKind::Remap,
+            DrawCommand::Shadow { .. } => PixelKind::Shadow,
+            DrawCommand::Outline { .. } => PixelKind::Outline,
+            DrawCommand::BlackOutline { .. } => PixelKind::BlackOutline,
+        }
+    }
+
+    /// Returns the relative index the command gives its `i`th pixel if it's a player color,
+    /// or 0 for everything else.
+    fn index(&self, i: usize) -> u8 {
+        match *self {
+            DrawCommand::PlayerColor { ref relative } => relative[i],
+            _ => 0,
+        }
+    }
+}
+
+/// The runs of each row of a shape, from top to bottom. Every row's runs together cover
+/// exactly the shape's width.
+pub type SlpDrawCommands = Vec<Vec<DrawCommand>>;
+
+/// Iterates over every pixel `commands` cover in row-major order, as its kind and the relative
+/// index for player colors or 0 for everything else.
+pub(crate) fn command_pixels<'a>(commands: &'a [Vec<DrawCommand>])
+                                 -> impl Iterator<Item = (PixelKind, u8)> + 'a {
+    commands.iter().flat_map(|row| row.iter()).flat_map(|command| {
+        let kind = command.kind();
+        (0..command.len() as usize).map(move |i| (kind, command.index(i)))
+    })
+}
+
+/// Builds the runs of a row from the kind and index of each of its pixels, of which only the
+/// relative indices of player colors are kept.
+pub(crate) fn row_commands<I>(pixels: I) -> Vec<DrawCommand>
+    where I: IntoIterator<Item = (PixelKind, u8)>
+{
+    let mut row = Vec::new();
+    for (kind, index) in pixels {
+        push_command(&mut row,
+                     match kind {
+                         PixelKind::Skip => DrawCommand::Skip { count: 1 },
+                         PixelKind::Color => DrawCommand::Color { count: 1 },
+                         PixelKind::Remap => DrawCommand::PlayerColor { relative: vec![index] },
+                         PixelKind::Shadow => DrawCommand::Shadow { count: 1 },
+                         PixelKind::Outline => DrawCommand::Outline { count: 1 },
+                         PixelKind::BlackOutline => DrawCommand::BlackOutline { count: 1 },
+                     });
+    }
+    row
+}
+
+/// Appends `command` to the runs of a row, merging it into the last run if they draw alike.
+///
+/// Fills are kept apart, so they can be told from copies of the same index.
+pub(crate) fn push_command(row: &mut Vec<DrawCommand>, command: DrawCommand) {
+    if command.is_empty() {
+        return;
+    }
+    if let Some(last) = row.last_mut() {
+        match (last, &command) {
+            (&mut DrawCommand::Skip { ref mut count }, &DrawCommand::Skip { count: more }) |
+            (&mut DrawCommand::Color { ref mut count }, &DrawCommand::Color { count: more }) |
+            (&mut DrawCommand::Shadow { ref mut count }, &DrawCommand::Shadow { count: more }) |
+            (&mut DrawCommand::Outline { ref mut count }, &DrawCommand::Outline { count: more }) |
+            (&mut DrawCommand::BlackOutline { ref mut count },
+             &DrawCommand::BlackOutline { count: more }) => {
+                *count += more;
+                return;
+            }
+            (&mut DrawCommand::PlayerColor { relative: ref mut indices },
+             &DrawCommand::PlayerColor { relative: ref more }) => {
+                indices.extend_from_slice(more);
+                return;
+            }
+            _ => {}
+        }
+    }
+    row.push(command);
+}
+
+/// An extended (`0x0E`) command that is kept as-is rather than decoded into pixels, such as a
+/// render hint.
+///
+/// The pixels it covers are left in a `DrawCommand::Skip` run, and it's written back out
+/// unchanged when the shape is encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RawCommand {
+    /// Where in the shape the command was found.
+    pub x: u32,
+    pub y: u32,
+
+    /// How many pixels the command covers.
+    pub length: u32,
+
+    /// The command byte and any operands.
+    pub bytes: Vec<u8>,
+}
+
+/// A shape's outline table, row offsets and commands, exactly as they were read.
+///
+/// Writing a shape whose pixels haven't changed since it was read reuses these bytes
+/// instead of re-encoding, so reading and writing a file doesn't alter its layout.
+#[derive(Clone)]
+pub struct SlpRawShape {
+    /// Where `data` started in the file it was read from.
+    pub offset: u32,
+
+    /// Everything from the start of the shape's first table through its last command byte.
+    pub data: Vec<u8>,
+
+    /// The shape's [content_hash](struct.SlpLogicalShape.html#method.content_hash) when it was read.
+    pub content_hash: u64,
+}
+
+#[derive(Clone)]
 pub struct SlpLogicalShape {
     pub header: SlpShapeHeader,
     pub pixels: SlpPixels,
+
+    /// How each row is drawn, as runs of pixels drawn alike.
+    ///
+    /// The runs decide how every pixel is drawn and hold the relative index of player colors.
+    /// The palette indices of colors are only kept in `pixels`, while its player color entries
+    /// are resolved from the runs, so colors are edited through `pixels` and player colors
+    /// through the runs.
+    pub commands: SlpDrawCommands,
+
+    /// The encoded form of this shape, if it was read from a file and its tables and commands
+    /// were stored close together.
+    pub raw: Option<SlpRawShape>,
+
+    /// Extended commands that weren't decoded, in the order they were read.
+    pub raw_commands: Vec<RawCommand>,
+
+    /// For each row, the offset of the first command the decoder read, as found in the
+    /// row offset table.
+    ///
+    /// Transparent rows are never decoded, so their entry is 0.
+    pub resolved_row_offsets: Vec<u32>,
+
+    /// For each row, whether the outline table marks it as fully transparent.
+    ///
+    /// Only filled in for shapes that were decoded, and cleared once their rows are moved.
+    pub transparent_rows: Vec<bool>,
+
+    /// For each row, the `(left, right_padding)` pair from the outline table, as found when
+    /// decoding. Transparent rows keep whatever marker the file used.
+    pub outline: Vec<(u16, u16)>,
+}
+
+// The buffers can be huge, so only their lengths are shown
+impl fmt::Debug for SlpLogicalShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SlpLogicalShape")
+            .field("header", &self.header)
+            .field("pixels", &self.pixels.len())
+            .field("commands", &self.commands.len())
+            .field("raw", &self.raw.as_ref().map(|raw| raw.data.len()))
+            .field("raw_commands", &self.raw_commands.len())
+            .finish()
+    }
 }
 
 impl SlpLogicalShape {
@@ -129,252 +582,1767 @@ impl SlpLogicalShape {
         SlpLogicalShape {
             header: SlpShapeHeader::new(),
             pixels: SlpPixels::new(),
+            commands: SlpDrawCommands::new(),
+            raw: None,
+            raw_commands: Vec::new(),
+            resolved_row_offsets: Vec::new(),
+            transparent_rows: Vec::new(),
+            outline: Vec::new(),
         }
     }
-}
 
-enum SlpEncodedLength {
-    SixUpperBit,
-    FourUpperBit,
-    LargeLength,
-}
+    /// Builds a shape from its buffers, failing if they don't match the header's dimensions.
+    pub fn from_parts(header: SlpShapeHeader,
+                      pixels: SlpPixels,
+                      commands: SlpDrawCommands)
+                      -> Result<SlpLogicalShape> {
+        let mut shape = SlpLogicalShape::new();
+        shape.header = header;
+        shape.pixels = pixels;
+        shape.commands = commands;
+        try!(shape.verify());
+        Ok(shape)
+    }
 
-impl SlpEncodedLength {
-    fn decode<R: Read>(self, cmd_byte: u8, cursor: &mut R) -> Result<usize> {
-        match self {
-            SlpEncodedLength::SixUpperBit => {
-                let length = (cmd_byte >> 2) as usize;
-                if length == 0 {
-                    return Err(ErrorKind::BadLength.into());
-                }
-                Ok(length)
-            }
-            SlpEncodedLength::FourUpperBit => {
-                let mut length = (cmd_byte >> 4) as usize;
-                if length == 0 {
-                    length = try!(cursor.read_u8()) as usize;
-                }
-                Ok(length)
+    /// Hashes the shape's dimensions, pixels and commands.
+    ///
+    /// The hash is only meant for comparisons within a single run of a program.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.header.width.hash(&mut hasher);
+        self.header.height.hash(&mut hasher);
+        self.pixels.hash(&mut hasher);
+        self.commands.hash(&mut hasher);
+        self.raw_commands.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns the raw encoded form of this shape if it's still accurate, i.e. the pixels
+    /// haven't been edited since it was read.
+    pub fn unmodified_raw(&self) -> Option<&SlpRawShape> {
+        self.raw.as_ref().filter(|raw| raw.content_hash == self.content_hash())
+    }
+
+    /// Checks that the shape is internally consistent, as a shape built by hand might not be.
+    ///
+    /// `pixels` must have exactly `width * height` entries, `commands` one row of runs covering
+    /// `width` pixels for each row, and every raw command must lie within the shape.
+    pub fn verify(&self) -> Result<()> {
+        let (width, height) = (self.header.width, self.header.height);
+        let area = width as usize * height as usize;
+        if self.pixels.len() != area || self.commands.len() != height as usize {
+            return Err(ErrorKind::InvalidSlp(format!("a {}x{} shape should have {} pixels and {} \
+                                                      rows of commands, but has {} and {}",
+                                                     width,
+                                                     height,
+                                                     area,
+                                                     height,
+                                                     self.pixels.len(),
+                                                     self.commands.len()))
+                .into());
+        }
+        for (y, row) in self.commands.iter().enumerate() {
+            let row_width = row.iter().map(|command| command.len() as u64).sum::<u64>();
+            if row_width != width as u64 {
+                return Err(ErrorKind::InvalidSlp(format!("the commands of row {} cover {} \
+                                                          pixels rather than {}",
+                                                         y,
+                                                         row_width,
+                                                         width))
+                    .into());
             }
-            SlpEncodedLength::LargeLength => {
-                let mut length = ((cmd_byte & 0xF0) as usize) << 4;
-                length += try!(cursor.read_u8()) as usize;
-                Ok(length)
+        }
+        for command in &self.raw_commands {
+            if command.y >= height || command.x as u64 + command.length as u64 > width as u64 {
+                return Err(ErrorKind::InvalidSlp(format!("raw command at row {}, x {} with \
+                                                          length {} is outside the {}x{} shape",
+                                                         command.y,
+                                                         command.x,
+                                                         command.length,
+                                                         width,
+                                                         height))
+                    .into());
             }
         }
+        Ok(())
     }
-}
 
-/// An image container format written by Ensemble Studios for their "Genie" game engine.
-///
-/// An SLP is made up of a header and numerous frames (sometimes called "shapes").
-pub struct SlpFile {
-    pub header: SlpHeader,
-    pub shapes: Vec<SlpLogicalShape>,
+    /// Returns the shape's `(center_x, center_y)`, the point that's placed at the position the
+    /// shape is drawn at.
+    pub fn anchor(&self) -> (i32, i32) {
+        (self.header.center_x, self.header.center_y)
+    }
 
-    // TODO: Remove this from SlpFile.
-    // We shouldn't be comitting to a player index until we hit the fragment shader.
-    pub player_index: u8,
-}
+    /// Returns the `(x, y, width, height)` of the screen rectangle the shape covers when its
+    /// anchor is drawn at `origin`.
+    pub fn blit_rect(&self, origin: (i32, i32)) -> (i32, i32, u32, u32) {
+        let (center_x, center_y) = self.anchor();
+        (origin.0 - center_x, origin.1 - center_y, self.header.width, self.header.height)
+    }
 
-impl SlpFile {
-    pub fn new(player_index: u8) -> SlpFile {
-        SlpFile {
-            header: SlpHeader::new(),
-            shapes: Vec::new(),
-            player_index: player_index,
+    /// Returns true if nothing is drawn in row `y`.
+    ///
+    /// Rows marked transparent in a decoded shape's outline table are answered without
+    /// looking at their commands.
+    pub fn row_is_transparent(&self, y: u32) -> bool {
+        if self.transparent_rows.get(y as usize) == Some(&true) {
+            return true;
         }
+        self.commands[y as usize].iter().all(|command| command.kind() == PixelKind::Skip)
     }
 
-    // TODO: Implement writing
+    /// Returns the `(left, right_padding)` pair of row `y`, i.e. how many transparent pixels
+    /// there are before and after what's drawn in it.
+    ///
+    /// Decoded shapes return what the outline table holds. Otherwise the pair is worked out
+    /// from the commands, with `(0x8000, 0x8000)` for rows with nothing drawn in them.
+    pub fn row_outline(&self, y: u32) -> (u16, u16) {
+        if let Some(&outline) = self.outline.get(y as usize) {
+            return outline;
+        }
+        let row = &self.commands[y as usize];
+        let is_skip = |command: &DrawCommand| command.kind() == PixelKind::Skip;
+        if row.iter().all(&is_skip) {
+            return (0x8000, 0x8000);
+        }
+        let left = row.iter()
+            .take_while(|command| is_skip(command))
+            .map(DrawCommand::len)
+            .sum::<u32>();
+        let right = row.iter()
+            .rev()
+            .take_while(|command| is_skip(command))
+            .map(DrawCommand::len)
+            .sum::<u32>();
+        (left as u16, right as u16)
+    }
 
-    pub fn read_from_file<P: AsRef<Path>>(file_name: P, player_index: u8) -> Result<SlpFile> {
-        let file_name = file_name.as_ref();
-        let mut file = try!(File::open(file_name));
-        return SlpFile::read_from(&mut file, player_index);
+    /// Returns true if the shape is zero pixels wide or tall.
+    ///
+    /// Such shapes are read without looking at their tables, which are often garbage, so this
+    /// tells an intentionally empty frame apart from a corrupt one.
+    pub fn is_empty(&self) -> bool {
+        self.header.is_zero_area()
     }
 
-    pub fn read_from<R: Read + Seek>(cursor: &mut R, player_index: u8) -> Result<SlpFile> {
-        let mut slp_file = SlpFile::new(player_index);
-        slp_file.header = try!(SlpHeader::read_from(cursor));
-        for _shape_index in 0..slp_file.header.shape_count {
-            let mut shape = SlpLogicalShape::new();
-            shape.header = try!(SlpShapeHeader::read_from_file(cursor));
-            slp_file.shapes.push(shape);
-        }
+    /// Returns true if nothing is drawn in this shape, as is common for placeholder frames.
+    pub fn is_fully_transparent(&self) -> bool {
+        self.commands.iter().flat_map(|row| row.iter()).all(|c| c.kind() == PixelKind::Skip)
+    }
 
-        for shape in &mut slp_file.shapes {
-            try!(SlpFile::read_pixel_data(cursor, shape, player_index));
-        }
+    /// Returns how many pixels of the shape are drawn, shadows included.
+    pub fn drawn_pixel_count(&self) -> usize {
+        self.commands
+            .iter()
+            .flat_map(|row| row.iter())
+            .filter(|command| command.kind() != PixelKind::Skip)
+            .map(|command| command.len() as usize)
+            .sum()
+    }
 
-        Ok(slp_file)
+    /// Returns the fraction of the shape's `width * height` pixels that are drawn, from 0 to 1.
+    ///
+    /// Empty shapes have an occupancy of 0.
+    pub fn occupancy(&self) -> f32 {
+        let area = self.header.width as usize * self.header.height as usize;
+        if area == 0 {
+            return 0.0;
+        }
+        self.drawn_pixel_count() as f32 / area as f32
     }
 
-    fn read_pixel_data<R: Read + Seek>(cursor: &mut R,
-                                       shape: &mut SlpLogicalShape,
-                                       player_index: u8)
-                                       -> Result<()> {
-        let width = shape.header.width;
-        let height = shape.header.height;
+    /// Returns the `(left, top, right, bottom)` edges of the drawn pixels, relative to the
+    /// shape's anchor. `right` and `bottom` are exclusive.
+    ///
+    /// Returns `None` if the shape is fully transparent.
+    pub fn bounds_relative_to_center(&self) -> Option<(i32, i32, i32, i32)> {
+        let (center_x, center_y) = self.anchor();
+        self.bounds_where(|kind| kind != PixelKind::Skip).map(|(left, top, right, bottom)| {
+            (left as i32 - center_x,
+             top as i32 - center_y,
+             right as i32 - center_x,
+             bottom as i32 - center_y)
+        })
+    }
 
-        // Reserve and zero out pixel data
-        shape.pixels.resize((width * height) as usize, 0u8);
+    /// Returns `true` for every pixel that has a palette index, i.e. is a `Color` or `Remap`,
+    /// in row-major order.
+    ///
+    /// Shadows and outlines aren't part of the shape itself, so they're left out, which makes
+    /// this suitable for hit-testing.
+    pub fn alpha_mask(&self) -> Vec<bool> {
+        command_pixels(&self.commands).map(|(kind, _)| is_opaque(kind)).collect()
+    }
 
-        for y in 0..height {
-            let line_outline_offset = shape.header.shape_outline_offset + (y * size_of::<u32>() as u32);
+    /// Returns the `(left, top, right, bottom)` edges of the pixels in the
+    /// [alpha_mask](#method.alpha_mask), within the shape. `right` and `bottom` are exclusive.
+    ///
+    /// Returns `None` if there are no such pixels.
+    pub fn opaque_bounds(&self) -> Option<(u32, u32, u32, u32)> {
+        self.bounds_where(is_opaque).map(|(left, top, right, bottom)| {
+            (left as u32, top as u32, right as u32, bottom as u32)
+        })
+    }
 
-            try!(cursor.seek(SeekFrom::Start(line_outline_offset as u64)));
-            let mut x = try!(cursor.read_u16()) as u32;
-            let right_padding = try!(cursor.read_u16()) as u32;
-            if x == 0x8000 || right_padding == 0x8000 {
-                // Fully transparent; skip to next line
-                continue;
+    /// Returns the edges of the pixels whose kind satisfies `f`, as in
+    /// [opaque_bounds](#method.opaque_bounds).
+    fn bounds_where<F>(&self, f: F) -> Option<(usize, usize, usize, usize)>
+        where F: Fn(PixelKind) -> bool
+    {
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        for (y, row) in self.commands.iter().enumerate() {
+            let mut x = 0;
+            for command in row {
+                let end = x + command.len() as usize;
+                if f(command.kind()) && end > x {
+                    bounds = Some(match bounds {
+                        Some((left, top, right, bottom)) => {
+                            (cmp::min(left, x),
+                             cmp::min(top, y),
+                             cmp::max(right, end),
+                             cmp::max(bottom, y + 1))
+                        }
+                        None => (x, y, end, y + 1),
+                    });
+                }
+                x = end;
             }
+        }
+        bounds
+    }
 
-            // The shape_data_offset points to an array of offsets to actual pixel data
-            // Seek out the offset for the current Y coordinate
-            let shape_data_ptr_offset = shape.header.shape_data_offsets + (y * size_of::<u32>() as u32);
-            try!(cursor.seek(SeekFrom::Start(shape_data_ptr_offset as u64)));
-
-            // Read the offset and seek to it so we can see the actual data
-            let data_offset = try!(cursor.read_u32());
-            try!(cursor.seek(SeekFrom::Start(data_offset as u64)));
-
-            // TODO: Consider detecting endless loop when we loop more times than there are pixels
-            loop {
-                let cmd_byte = try!(cursor.read_u8());
-
-                // End of line indicator
-                if cmd_byte == 0x0F {
-                    if x != width - right_padding {
-                        return Err(ErrorKind::InvalidSlp(format!("Line {} not the expected \
-                                                                  size. Was {} but should be {}",
-                                                                 y,
-                                                                 x,
-                                                                 width - right_padding))
-                            .into());
-                    }
-                    break;
-                }
+    /// Counts how many `Color` pixels use each palette index.
+    pub fn index_histogram(&self) -> [u32; 256] {
+        let mut histogram = [0u32; 256];
+        for (kind, index) in self.drawn_pixels() {
+            if kind == PixelKind::Color {
+                histogram[index as usize] += 1;
+            }
+        }
+        histogram
+    }
 
-                if x > width {
-                    return Err(ErrorKind::InvalidSlp("Unexpected error occurred.
-                        Line length already exceeded before stop."
-                            .into())
-                        .into());
-                }
+    /// Returns `pixels` with player colors resolved to `player_index`'s palette entries, i.e.
+    /// every remap pixel as `player_index * 16 + relative_index`.
+    ///
+    /// This works whether or not a player was baked in when the shape was decoded.
+    pub fn apply_player(&self, player_index: u8) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .zip(command_pixels(&self.commands))
+            .map(|(&pixel, (kind, index))| match kind {
+                PixelKind::Remap => remap_index(player_index, index, 0),
+                _ => pixel,
+            })
+            .collect()
+    }
 
-                use self::SlpEncodedLength::*;
-
-                // SLP opcodes are stored in the lowest 4 bits of the command byte.
-                let opcode = cmd_byte & 0b00001111;
-
-                match opcode {
-                    // Block copy
-                    0b0000 |
-                    0b0100 |
-                    0b1000 |
-                    0b1100 => {
-                        let length = try!(SixUpperBit.decode(cmd_byte, cursor));
-                        for _ in 0..length {
-                            shape.pixels[(y * width + x) as usize] = try!(cursor.read_u8());
-                            x += 1;
-                        }
-                    }
+    /// Rewrites the shape's player color pixels in place with `new_player`'s colors, e.g. when
+    /// a unit changes hands.
+    ///
+    /// The relative indices are taken from the `PlayerColor` runs, so this works whatever
+    /// player and `player_color_base` the shape was decoded with. Unlike
+    /// [apply_player](#method.apply_player), this doesn't allocate.
+    pub fn recolor(&mut self, new_player: u8) {
+        for (pixel, (kind, relative)) in self.pixels.iter_mut().zip(command_pixels(&self.commands)) {
+            if kind == PixelKind::Remap {
+                *pixel = remap_index(new_player, relative, 0);
+            }
+        }
+    }
 
-                    // Skip pixels
-                    0b0001 |
-                    0b0101 |
-                    0b1001 |
-                    0b1101 => {
-                        x += try!(SixUpperBit.decode(cmd_byte, cursor)) as u32;
-                    }
+    /// Returns `true` if any pixel of the shape is a player color.
+    pub fn has_player_color(&self) -> bool {
+        self.commands.iter().flat_map(|row| row.iter()).any(|c| c.kind() == PixelKind::Remap)
+    }
 
-                    // Large block copy
-                    0b0010 => {
-                        let length = try!(LargeLength.decode(cmd_byte, cursor));
-                        for _ in 0..length {
-                            shape.pixels[(y * width + x) as usize] = try!(cursor.read_u8());
-                            x += 1;
-                        }
-                    }
+    /// Guesses whether the shape is a grayscale mask, such as fog or a construction overlay,
+    /// rather than full-color art.
+    ///
+    /// This is best-effort: a shape counts as a mask if it's drawn entirely as shadow, or if it
+    /// has no player colors and all of its colors lie within one 16-entry span of the palette,
+    /// which is how the game palettes lay out the shades of a single ramp.
+    pub fn looks_like_mask(&self) -> bool {
+        if self.has_player_color() {
+            return false;
+        }
+        let histogram = self.index_histogram();
+        let first = histogram.iter().position(|&count| count > 0);
+        let last = histogram.iter().rposition(|&count| count > 0);
+        match (first, last) {
+            (Some(first), Some(last)) => last - first < 16,
+            _ => {
+                self.commands
+                    .iter()
+                    .flat_map(|row| row.iter())
+                    .any(|command| command.kind() == PixelKind::Shadow)
+            }
+        }
+    }
 
-                    // Large skip pixels
-                    0b0011 => {
-                        let length = try!(LargeLength.decode(cmd_byte, cursor));
-                        x += length as u32;
-                    }
+    /// Returns `true` for every pixel that's part of the shape's shadow, in row-major order.
+    ///
+    /// This lets the shadow be drawn as its own layer, separately from the rest of the shape.
+    pub fn shadow_mask(&self) -> Vec<bool> {
+        command_pixels(&self.commands).map(|(kind, _)| kind == PixelKind::Shadow).collect()
+    }
 
-                    // Copy and colorize block
-                    0b0110 => {
-                        let length = try!(FourUpperBit.decode(cmd_byte, cursor));
+    /// Returns roughly how many bytes of memory this shape occupies, including its buffers.
+    ///
+    /// Decoded shapes keep `width * height` pixels no matter how much of the shape is actually
+    /// drawn, so this can be much larger than the shape's encoded size.
+    pub fn memory_size(&self) -> usize {
+        size_of::<SlpLogicalShape>() + self.pixels.capacity() +
+        self.commands.capacity() * size_of::<Vec<DrawCommand>>() +
+        self.commands.iter().map(|row| row.capacity() * size_of::<DrawCommand>()).sum::<usize>() +
+        self.commands
+            .iter()
+            .flat_map(|row| row.iter())
+            .map(|command| match *command {
+                DrawCommand::PlayerColor { ref relative } => relative.capacity(),
+                _ => 0,
+            })
+            .sum::<usize>() +
+        self.resolved_row_offsets.capacity() * size_of::<u32>() +
+        self.transparent_rows.capacity() * size_of::<bool>() +
+        self.outline.capacity() * size_of::<(u16, u16)>() +
+        self.raw.as_ref().map_or(0, |raw| raw.data.capacity()) +
+        self.raw_commands.capacity() * size_of::<RawCommand>() +
+        self.raw_commands.iter().map(|command| command.bytes.capacity()).sum::<usize>()
+    }
 
-                        for _ in 0..length {
-                            let relative_index = try!(cursor.read_u8());
-                            let player_color = player_index * 16 + relative_index;
-                            shape.pixels[(y * width + x) as usize] = player_color | relative_index;
-                            x += 1;
-                        }
-                    }
+    /// Moves every `Color` pixel whose index is in `from` to the same position in the range
+    /// starting at `to_base`.
+    ///
+    /// Player color, shadow and transparent pixels are left alone.
+    pub fn swap_index_range(&mut self, from: Range<u8>, to_base: u8) {
+        for (pixel, (kind, _)) in self.pixels.iter_mut().zip(command_pixels(&self.commands)) {
+            if kind == PixelKind::Color && from.start <= *pixel && *pixel < from.end {
+                *pixel = to_base.wrapping_add(*pixel - from.start);
+            }
+        }
+    }
 
-                    // Fill block
-                    0b0111 => {
-                        let length = try!(FourUpperBit.decode(cmd_byte, cursor));
-                        let color = try!(cursor.read_u8());
-                        for _ in 0..length {
-                            shape.pixels[(y * width + x) as usize] = color;
-                            x += 1;
-                        }
-                    }
+    /// Changes the shape's dimensions without scaling it.
+    ///
+    /// The existing content is moved by `(offset_x, offset_y)` within the new canvas and
+    /// anything that falls outside of it is clipped. Newly exposed pixels are transparent.
+    /// The center is moved along with the content, so the shape still lines up when drawn.
+    pub fn resize_canvas(&mut self, new_width: u32, new_height: u32, offset_x: i32, offset_y: i32) {
+        let (pixels, commands) = self.moved_pixels(new_width, new_height, |x, y| {
+            let new_x = x as i64 + offset_x as i64;
+            let new_y = y as i64 + offset_y as i64;
+            if new_x < 0 || new_y < 0 || new_x >= new_width as i64 || new_y >= new_height as i64 {
+                return None;
+            }
+            Some((new_x as u32, new_y as u32))
+        });
 
-                    // Transform block
-                    0b1010 => {
-                        let length = try!(FourUpperBit.decode(cmd_byte, cursor));
-                        let relative_index = try!(cursor.read_u8());
-                        let player_color = player_index * 16 + relative_index;
+        self.pixels = pixels;
+        self.commands = commands;
+        self.clear_row_tables();
+        self.header.width = new_width;
+        self.header.height = new_height;
+        self.header.center_x += offset_x;
+        self.header.center_y += offset_y;
+    }
 
-                        for _ in 0..length {
-                            shape.pixels[(y * width + x) as usize] = player_color | relative_index;
-                            x += 1;
-                        }
-                    }
+    /// Rotates the shape a quarter turn, swapping its width and height and moving the center
+    /// along with the pixels.
+    ///
+    /// Raw commands only make sense along rows, so they're dropped.
+    pub fn rotate_90(&mut self, clockwise: bool) {
+        let (width, height) = (self.header.width, self.header.height);
+        let (pixels, commands) = self.moved_pixels(height, width, |x, y| if clockwise {
+            Some((height - 1 - y, x))
+        } else {
+            Some((y, width - 1 - x))
+        });
 
-                    // Shadow pixels
-                    0b1011 => {
-                        let length = try!(FourUpperBit.decode(cmd_byte, cursor));
-                        // TODO: Render the shadow instead of skipping
-                        // The length is determined as in cases 6, 7 and 0x0a. For the length
-                        // of the run, the destination pixels already in the buffer are used
-                        // as a lookup into a "shadow table" and this lookup pixel is then
-                        // used to draw into the buffer. The shadow table is typically a
-                        // color-tinted variation of the real color table, and is generally
-                        // used to draw things like the red-tinted checkerboard sprites when
-                        // you try to place a building in an area where it cannot be placed.
-                        x += length as u32;
-                        println!("TODO: skipped {} instead of drawing the shadow", length);
-                    }
+        let (center_x, center_y) = self.anchor();
+        let (new_center_x, new_center_y) = if clockwise {
+            (height as i32 - center_y, center_x)
+        } else {
+            (center_y, width as i32 - center_x)
+        };
+        self.pixels = pixels;
+        self.commands = commands;
+        self.clear_row_tables();
+        self.raw_commands.clear();
+        self.header.width = height;
+        self.header.height = width;
+        self.header.center_x = new_center_x;
+        self.header.center_y = new_center_y;
+    }
 
-                    // Extended
-                    0b1110 => {
-                        // The extended opcode lives in the top 4 bits of the command byte (yes, I lied above).
-                        let opcode = cmd_byte & 0b11110000;
-                        panic!("Extended (0x0E) not implemented (cmd_byte={}, opcode={})", cmd_byte, opcode);
-                    }
+    /// Forgets the row tables found when decoding, which no longer describe the shape once its
+    /// rows have moved.
+    fn clear_row_tables(&mut self) {
+        self.resolved_row_offsets.clear();
+        self.transparent_rows.clear();
+        self.outline.clear();
+    }
 
-                    _ => panic!("unknown command: {}", cmd_byte),
-                }
+    /// Moves every pixel to wherever `f` puts it on a blank `new_width` by `new_height`
+    /// canvas, or drops it if `f` returns `None`, returning the canvas' pixels and commands.
+    fn moved_pixels<F>(&self, new_width: u32, new_height: u32, f: F) -> (SlpPixels, SlpDrawCommands)
+        where F: Fn(u32, u32) -> Option<(u32, u32)>
+    {
+        let new_width = new_width as usize;
+        let area = new_width * new_height as usize;
+        let mut pixels = vec![0u8; area];
+        let mut drawn = vec![(PixelKind::Skip, 0u8); area];
+        for ((x, y, _, pixel), kind_and_index) in self.enumerate_pixels().zip(self.drawn_pixels()) {
+            if let Some((new_x, new_y)) = f(x, y) {
+                let loc = new_y as usize * new_width + new_x as usize;
+                pixels[loc] = pixel;
+                drawn[loc] = kind_and_index;
             }
         }
-        Ok(())
+        let commands = (0..new_height as usize)
+            .map(|y| row_commands(drawn[y * new_width..(y + 1) * new_width].iter().cloned()))
+            .collect();
+        (pixels, commands)
+    }
+
+    /// Returns true if `other` is drawn exactly like this shape, at the same place, with the
+    /// same palette and properties. Where their data is stored doesn't matter.
+    fn draws_like(&self, other: &SlpLogicalShape) -> bool {
+        let (a, b) = (&self.header, &other.header);
+        a.palette_offset == b.palette_offset && a.properties == b.properties &&
+        a.width == b.width && a.height == b.height && a.center_x == b.center_x &&
+        a.center_y == b.center_y && self.pixels == other.pixels &&
+        self.commands == other.commands && self.raw_commands == other.raw_commands
+    }
+
+    /// Iterates over every pixel in row-major order as its kind and index: the entry in `pixels`
+    /// for colors, the relative index from its run for player colors and 0 for everything else.
+    pub(crate) fn drawn_pixels<'a>(&'a self) -> impl Iterator<Item = (PixelKind, u8)> + 'a {
+        command_pixels(&self.commands)
+            .zip(self.pixels.iter())
+            .map(|((kind, index), &pixel)| match kind {
+                PixelKind::Color => (kind, pixel),
+                _ => (kind, index),
+            })
+    }
+
+    /// Iterates over every pixel in row-major order as `(x, y, kind, index)`.
+    pub fn enumerate_pixels<'a>(&'a self) -> impl Iterator<Item = (u32, u32, PixelKind, u8)> + 'a {
+        let width = self.header.width;
+        command_pixels(&self.commands)
+            .zip(self.pixels.iter())
+            .enumerate()
+            .map(move |(i, ((kind, _), &index))| (i as u32 % width, i as u32 / width, kind, index))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{SlpHeader, ErrorKind};
+/// Decoding for this player leaves player colors unresolved.
+///
+/// Remap pixels then hold just their relative index, and are only told apart from palette
+/// entries by their `DrawCommand::PlayerColor` run. Use
+/// [to_rgba](struct.SlpLogicalShape.html#method.to_rgba) to resolve them for a real player.
+pub const NO_PLAYER: u8 = 0;
 
-    #[test]
-    fn test_slp_header_read_from() {
+/// Returns the value a remap pixel is stored as when decoding for `player_index`: the start of
+/// the player's block of 16 palette entries, counted from `base`, plus the relative index.
+pub(crate) fn remap_index(player_index: u8, relative_index: u8, base: u8) -> u8 {
+    if player_index == NO_PLAYER {
+        return relative_index;
+    }
+    base.wrapping_add(player_index.wrapping_mul(16)).wrapping_add(relative_index)
+}
+
+fn is_opaque(kind: PixelKind) -> bool {
+    kind == PixelKind::Color || kind == PixelKind::Remap
+}
+
+/// Where a row that isn't transparent starts, as found in a shape's outline and row offset
+/// tables.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RowStart {
+    pub left: u32,
+    pub right_padding: u32,
+    pub offset: u32,
+}
+
+/// Everything decoding a shape's rows needs besides the shape and its row starts.
+pub(crate) struct DecodeContext<'a, 'w: 'a> {
+    pub player_index: u8,
+    pub options: &'a DecodeOptions,
+    /// The length of the stream, which no offset may point past.
+    pub len: u64,
+    pub trace: &'a mut Option<&'w mut dyn Write>,
+}
+
+/// Fails if `header` is larger than `options` allow, before any buffers are allocated for it.
+pub(crate) fn check_dimensions(header: &SlpShapeHeader, options: &DecodeOptions) -> Result<()> {
+    if header.width > options.max_dimension || header.height > options.max_dimension {
+        return Err(ErrorKind::InvalidSlp(format!("shape is {}x{}, which exceeds the maximum \
+                                                  dimension of {}",
+                                                 header.width,
+                                                 header.height,
+                                                 options.max_dimension))
+            .into());
+    }
+    Ok(())
+}
+
+/// Returns true if `cmd_byte` ends a row.
+///
+/// Some encoders set the upper bits of the standard `0x0F` marker, so only its opcode is
+/// compared. Nonstandard markers have to match exactly.
+fn is_end_of_line(cmd_byte: u8, options: &DecodeOptions) -> bool {
+    if options.end_of_line == 0x0F {
+        cmd_byte & 0x0F == 0x0F
+    } else {
+        cmd_byte == options.end_of_line
+    }
+}
+
+/// Returns the offset of row `y`'s entry in a table of `u32`-sized entries at `table_offset`.
+///
+/// Both come from the file, so this fails with `InvalidSlp` rather than wrapping around when
+/// the result doesn't fit in 32 bits.
+fn table_entry_offset(table_offset: u32, y: u32) -> Result<u32> {
+    y.checked_mul(size_of::<u32>() as u32)
+        .and_then(|entry| table_offset.checked_add(entry))
+        .ok_or_else(|| {
+            ErrorKind::InvalidSlp(format!("row {}'s entry in the table at offset {} is past the \
+                                           largest possible offset",
+                                          y,
+                                          table_offset))
+                .into()
+        })
+}
+
+/// Checks that a run of `length` pixels starting at `x` fits within a row `width` pixels wide.
+fn check_run(x: u32, length: u32, width: u32, y: u32) -> Result<()> {
+    if x as u64 + length as u64 > width as u64 {
+        return Err(ErrorKind::InvalidSlp(format!("run of {} pixels at row {}, x {} doesn't fit \
+                                                  in {} pixels",
+                                                 length,
+                                                 y,
+                                                 x,
+                                                 width))
+            .into());
+    }
+    Ok(())
+}
+
+/// Stores the decoded pixels of a run starting at `(x, y)` wherever `layout` puts them.
+///
+/// Does nothing if the shape's buffers were left empty, as they are when only validating.
+fn put_run<I>(shape: &mut SlpLogicalShape, layout: PixelLayout, x: u32, y: u32, pixels: I)
+    where I: IntoIterator<Item = u8>
+{
+    if shape.pixels.is_empty() {
+        return;
+    }
+    let (width, height) = (shape.header.width, shape.header.height);
+    for (i, pixel) in pixels.into_iter().enumerate() {
+        shape.pixels[layout.index(x + i as u32, y, width, height)] = pixel;
+    }
+}
+
+/// Fails with `err`, pointing I/O failures at the row they happened in.
+fn row_error<T>(err: Error, shape_index: usize, y: u32) -> Result<T> {
+    match err {
+        Error(ErrorKind::IoError(err), _) => {
+            Err(err).chain_err(|| ErrorKind::ShapeReadError(shape_index, y))
+        }
+        Error(ErrorKind::InvalidSlp(reason), state) => {
+            Err(Error(ErrorKind::DecodeError(shape_index, y, reason), state))
+        }
+        err => Err(err),
+    }
+}
+
+/// Seeks to `offset`, failing with `InvalidSlp` if it's past the end of a `len` byte stream.
+///
+/// Seeking past the end succeeds on most streams, which would otherwise surface as a confusing
+/// read error somewhere later.
+pub(crate) fn seek_checked<R: Seek>(cursor: &mut R, offset: u64, len: u64) -> Result<()> {
+    if offset > len {
+        return Err(ErrorKind::InvalidSlp(format!("offset {} is past the end of the {} byte file",
+                                                 offset,
+                                                 len))
+            .into());
+    }
+    try!(cursor.seek(SeekFrom::Start(offset)));
+    Ok(())
+}
+
+/// A view of a stream that starts `base` bytes in, so that the offsets of an SLP embedded in
+/// a larger file, such as a DRS archive, can be used as they are.
+struct OffsetStream<'a, R: 'a> {
+    inner: &'a mut R,
+    base: u64,
+}
+
+impl<'a, R: Read> Read for OffsetStream<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<'a, R: Seek> Seek for OffsetStream<'a, R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(offset) => {
+                match self.base.checked_add(offset) {
+                    Some(offset) => SeekFrom::Start(offset),
+                    None => {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                  "seek offset overflows"))
+                    }
+                }
+            }
+            pos => pos,
+        };
+        let position = try!(self.inner.seek(pos));
+        if position < self.base {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "seek to before the start of the SLP"));
+        }
+        Ok(position - self.base)
+    }
+}
+
+/// Decodes the first shape of the SLP in `data`, leaving player colors as their relative
+/// (0-15) index.
+///
+/// This never panics: any malformed input, however it's malformed, gives an `Err`. That makes
+/// it the entry point for fuzzing the decoder. Version 4 files fail with `UnsupportedVersion`.
+pub fn decode_frame_from_slice(data: &[u8]) -> Result<SlpLogicalShape> {
+    SlpFile::read_shape_from(&mut io::Cursor::new(data), 0, NO_PLAYER)
+}
+
+/// Returns the length of `cursor`'s stream, leaving its position unchanged.
+pub(crate) fn stream_len<R: Seek>(cursor: &mut R) -> Result<u64> {
+    let position = try!(cursor.stream_position());
+    let len = try!(cursor.seek(SeekFrom::End(0)));
+    try!(cursor.seek(SeekFrom::Start(position)));
+    Ok(len)
+}
+
+/// Returns a human readable name for the command encoded in `cmd_byte`.
+pub(crate) fn command_name(cmd_byte: u8) -> &'static str {
+    match cmd_byte & 0b00001111 {
+        0b0000 | 0b0100 | 0b1000 | 0b1100 => "block copy",
+        0b0001 | 0b0101 | 0b1001 | 0b1101 => "skip",
+        0b0010 => "large block copy",
+        0b0011 => "large skip",
+        0b0110 => "copy and colorize",
+        0b0111 => "fill",
+        0b1010 => "transform",
+        0b1011 => "shadow",
+        0b1110 => "extended",
+        _ => "end of line",
+    }
+}
+
+pub(crate) enum SlpEncodedLength {
+    SixUpperBit,
+    FourUpperBit,
+    LargeLength,
+}
+
+impl SlpEncodedLength {
+    /// Decodes the run length of `cmd_byte`, which was found at `(x, y)`.
+    pub(crate) fn decode<R: Read>(self,
+                                  cmd_byte: u8,
+                                  x: u32,
+                                  y: u32,
+                                  cursor: &mut R)
+                                  -> Result<usize> {
+        match self {
+            SlpEncodedLength::SixUpperBit => {
+                let length = (cmd_byte >> 2) as usize;
+                if length == 0 {
+                    return Err(ErrorKind::BadLength(cmd_byte, y, x).into());
+                }
+                Ok(length)
+            }
+            SlpEncodedLength::FourUpperBit => {
+                let mut length = (cmd_byte >> 4) as usize;
+                if length == 0 {
+                    length = try!(cursor.read_u8()) as usize;
+                }
+                Ok(length)
+            }
+            SlpEncodedLength::LargeLength => {
+                let mut length = ((cmd_byte & 0xF0) as usize) << 4;
+                length += try!(cursor.read_u8()) as usize;
+                Ok(length)
+            }
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a SlpFile {
+    type Item = &'a SlpLogicalShape;
+    type IntoIter = slice::Iter<'a, SlpLogicalShape>;
+
+    fn into_iter(self) -> slice::Iter<'a, SlpLogicalShape> {
+        self.shapes.iter()
+    }
+}
+
+/// An image container format written by Ensemble Studios for their "Genie" game engine.
+///
+/// An SLP is made up of a header and numerous frames (sometimes called "shapes").
+#[derive(Debug, Clone)]
+pub struct SlpFile {
+    pub header: SlpHeader,
+    pub shapes: Vec<SlpLogicalShape>,
+}
+
+impl Default for SlpFile {
+    fn default() -> SlpFile {
+        SlpFile {
+            header: SlpHeader::new(),
+            shapes: Vec::new(),
+        }
+    }
+}
+
+impl SlpFile {
+    pub fn new() -> SlpFile {
+        SlpFile::default()
+    }
+
+    /// Returns the number of shapes in the file.
+    ///
+    /// This is how many were decoded, which is what `header.shape_count` says for files that
+    /// were read and haven't been modified since.
+    pub fn shape_count(&self) -> usize {
+        self.shapes.len()
+    }
+
+    pub fn shapes_iter(&self) -> slice::Iter<'_, SlpLogicalShape> {
+        self.shapes.iter()
+    }
+
+    /// Returns how many directions the file's header says its animation has.
+    ///
+    /// Only version 4 files record this; for others, the number of directions depends on
+    /// what the file is used for, typically 5 or 8 for units.
+    pub fn num_directions(&self) -> Option<usize> {
+        self.header.extended.map(|extended| extended.num_directions as usize)
+    }
+
+    /// Splits the shapes into `directions` equally long animations, one per direction, in the
+    /// order they're stored in.
+    ///
+    /// Fails with `InvalidSlp` if the shapes can't be split evenly.
+    pub fn group_by_directions(&self, directions: usize) -> Result<Vec<&[SlpLogicalShape]>> {
+        if directions == 0 || self.shapes.len() % directions != 0 {
+            return Err(ErrorKind::InvalidSlp(format!("{} shapes can't be split into {} \
+                                                      directions",
+                                                     self.shapes.len(),
+                                                     directions))
+                .into());
+        }
+        let frames = self.shapes.len() / directions;
+        if frames == 0 {
+            return Ok(vec![&[][..]; directions]);
+        }
+        Ok(self.shapes.chunks(frames).collect())
+    }
+
+    /// Returns roughly how many bytes of memory this file and all of its shapes occupy.
+    pub fn memory_size(&self) -> usize {
+        let unused_shapes = self.shapes.capacity() - self.shapes.len();
+        size_of::<SlpFile>() + unused_shapes * size_of::<SlpLogicalShape>() +
+        self.shapes.iter().map(|shape| shape.memory_size()).sum::<usize>()
+    }
+
+    /// Returns the indices of the shapes that contain player colors, and so will look different
+    /// for each player.
+    pub fn player_color_frames(&self) -> Vec<usize> {
+        self.shapes
+            .iter()
+            .enumerate()
+            .filter(|&(_, shape)| shape.has_player_color())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Returns every shape's [anchor](struct.SlpLogicalShape.html#method.anchor), in order.
+    pub fn frame_anchors(&self) -> Vec<(i32, i32)> {
+        self.shapes.iter().map(|shape| shape.anchor()).collect()
+    }
+
+    /// Returns the union of every shape's
+    /// [bounds_relative_to_center](struct.SlpLogicalShape.html#method.bounds_relative_to_center),
+    /// the region an animation drawn at one position will cover.
+    ///
+    /// Returns all zeroes if every shape is fully transparent.
+    pub fn animation_bounds(&self) -> (i32, i32, i32, i32) {
+        self.shapes
+            .iter()
+            .filter_map(|shape| shape.bounds_relative_to_center())
+            .fold(None, |union, bounds| {
+                Some(match union {
+                    Some((left, top, right, bottom)) => {
+                        (cmp::min(left, bounds.0),
+                         cmp::min(top, bounds.1),
+                         cmp::max(right, bounds.2),
+                         cmp::max(bottom, bounds.3))
+                    }
+                    None => bounds,
+                })
+            })
+            .unwrap_or((0, 0, 0, 0))
+    }
+
+    /// Removes shapes that are identical to the shape before them, including their centers,
+    /// palettes and properties.
+    ///
+    /// Returns, for each original shape index, the index of the shape it now corresponds to,
+    /// so animations can keep their timing by showing the remaining shape for longer.
+    pub fn dedup_frames(&mut self) -> Vec<usize> {
+        let mut mapping = Vec::with_capacity(self.shapes.len());
+        let mut kept: Vec<SlpLogicalShape> = Vec::with_capacity(self.shapes.len());
+        let mut last_hash = None;
+        for shape in self.shapes.drain(..) {
+            // The hash only rules out most shapes quickly; the rest are compared in full
+            let hash = shape.content_hash();
+            let duplicate = match kept.last() {
+                Some(last) => last_hash == Some(hash) && last.draws_like(&shape),
+                None => false,
+            };
+            if !duplicate {
+                kept.push(shape);
+                last_hash = Some(hash);
+            }
+            mapping.push(kept.len() - 1);
+        }
+        self.shapes = kept;
+        self.header.shape_count = self.shapes.len() as u32;
+        mapping
+    }
+
+    /// Decodes the SLP at `file_name`, leaving player colors as their relative index like
+    /// [read_from](#method.read_from).
+    pub fn read_from_file<P: AsRef<Path>>(file_name: P) -> Result<SlpFile> {
+        SlpFile::read_from_file_baked(file_name, NO_PLAYER)
+    }
+
+    /// Decodes the SLP at `file_name` with `player_index`'s colors baked in, like
+    /// [read_from_baked](#method.read_from_baked).
+    pub fn read_from_file_baked<P: AsRef<Path>>(file_name: P, player_index: u8) -> Result<SlpFile> {
+        let file_name = file_name.as_ref();
+        let mut file = try!(File::open(file_name));
+        let len = try!(file.metadata()).len();
+        SlpFile::read_from_with_len(&mut file, len, player_index)
+    }
+
+    /// Decodes the file at `file_name` by memory-mapping it instead of reading it into memory.
+    ///
+    /// The file is decoded in place, so it must not be modified or truncated by anyone else
+    /// until this returns. Doing so can produce garbage shapes or, on some platforms, kill the
+    /// process with a bus error.
+    #[cfg(feature = "memmap")]
+    pub fn read_from_mmap<P: AsRef<Path>>(file_name: P, player_index: u8) -> Result<SlpFile> {
+        let file = try!(File::open(file_name.as_ref()));
+        // Safety: see the doc comment; we can't stop other processes from changing the file.
+        let map = try!(unsafe { ::memmap::Mmap::map(&file) });
+        SlpFile::read_from_baked(&mut io::Cursor::new(&map[..]), player_index)
+    }
+
+    /// Decodes a whole SLP, leaving player colors as their relative (0-15) index.
+    ///
+    /// Use [apply_player](struct.SlpLogicalShape.html#method.apply_player) to resolve them for a
+    /// player, or [read_from_baked](#method.read_from_baked) to do so while decoding.
+    pub fn read_from<R: Read + Seek>(cursor: &mut R) -> Result<SlpFile> {
+        SlpFile::read_from_with_options(cursor, NO_PLAYER, &DecodeOptions::default())
+    }
+
+    /// Decodes a whole SLP with whichever parser handles its version.
+    ///
+    /// Only `2.0N` files are supported so far; anything else fails with `UnsupportedVersion`.
+    /// That includes `4.0N` and `4.0X` files, whose headers can be read with
+    /// [read_nth_header](#method.read_nth_header) but whose pixel data can't be decoded yet.
+    pub fn autodetect<R: Read + Seek>(cursor: &mut R, player_index: u8) -> Result<SlpFile> {
+        let position = try!(cursor.stream_position());
+        let mut header = SlpHeader::new();
+        try!(cursor.read_exact(&mut header.file_version));
+        try!(cursor.seek(SeekFrom::Start(position)));
+        match &header.file_version {
+            b"2.0N" => SlpFile::read_from_baked(cursor, player_index),
+            _ => Err(ErrorKind::UnsupportedVersion(header.version_str().into_owned()).into()),
+        }
+    }
+
+    /// Decodes a whole SLP with `player_index`'s colors baked into `pixels`, i.e. every remap
+    /// pixel is stored as `player_index * 16 + relative_index`.
+    pub fn read_from_baked<R: Read + Seek>(cursor: &mut R, player_index: u8) -> Result<SlpFile> {
+        SlpFile::read_from_with_options(cursor, player_index, &DecodeOptions::default())
+    }
+
+    /// Decodes an SLP from a stream that can't seek, such as a network or decompression
+    /// stream, with `player_index`'s colors baked in as by
+    /// [read_from_baked](#method.read_from_baked).
+    ///
+    /// The whole stream is read into memory first, so this needs as much memory again as the
+    /// encoded file on top of the decoded shapes. Prefer [read_from](#method.read_from) for
+    /// streams that can seek.
+    pub fn read_from_buffered<R: Read>(reader: &mut R, player_index: u8) -> Result<SlpFile> {
+        let mut data = Vec::new();
+        try!(reader.read_to_end(&mut data));
+        SlpFile::read_from_bytes(&data, player_index)
+    }
+
+    /// Decodes each `(offset, length)` range of `data` as a separate SLP, such as the entries
+    /// of a DRS archive, with `player_index`'s colors baked in as by
+    /// [read_from_baked](#method.read_from_baked).
+    ///
+    /// Every range gets its own result, so one corrupt or out of bounds entry doesn't stop the
+    /// others from being read.
+    pub fn read_many(data: &[u8],
+                     ranges: &[(usize, usize)],
+                     player_index: u8)
+                     -> Vec<Result<SlpFile>> {
+        ranges.iter()
+            .map(|&(offset, length)| {
+                let range = offset.checked_add(length).and_then(|end| data.get(offset..end));
+                match range {
+                    Some(slp) => SlpFile::read_from_bytes(slp, player_index),
+                    None => {
+                        Err(ErrorKind::InvalidSlp(format!("{} bytes at offset {} are outside \
+                                                           the {} byte buffer",
+                                                          length,
+                                                          offset,
+                                                          data.len()))
+                            .into())
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Decodes an SLP that starts `base_offset` bytes into `cursor`, such as one inside a DRS
+    /// archive, with `player_index`'s colors baked in as by
+    /// [read_from_baked](#method.read_from_baked).
+    ///
+    /// The SLP's offsets are taken relative to `base_offset`, so it doesn't need to be copied
+    /// out first. Anything after the SLP is treated as part of it, as if it were padding.
+    pub fn read_from_at<R: Read + Seek>(cursor: &mut R,
+                                        base_offset: u64,
+                                        player_index: u8)
+                                        -> Result<SlpFile> {
+        let mut stream = OffsetStream {
+            inner: cursor,
+            base: base_offset,
+        };
+        try!(stream.seek(SeekFrom::Start(0)));
+        SlpFile::read_from_baked(&mut stream, player_index)
+    }
+
+    /// Decodes an SLP that's already in memory, with `player_index`'s colors baked in as by
+    /// [read_from_baked](#method.read_from_baked).
+    ///
+    /// With the `parallel` feature, the shapes are decoded concurrently.
+    pub fn read_from_bytes(data: &[u8], player_index: u8) -> Result<SlpFile> {
+        #[cfg(feature = "parallel")]
+        return SlpFile::read_from_bytes_parallel(data, player_index);
+        #[cfg(not(feature = "parallel"))]
+        return SlpFile::read_from_with_len(&mut io::Cursor::new(data),
+                                           data.len() as u64,
+                                           player_index);
+    }
+
+    /// Reads every shape header, then decodes the shapes on rayon's thread pool, each with its
+    /// own cursor over `data`.
+    #[cfg(feature = "parallel")]
+    fn read_from_bytes_parallel(data: &[u8], player_index: u8) -> Result<SlpFile> {
+        use rayon::prelude::*;
+
+        let len = data.len() as u64;
+        let (header, shape_headers) = try!(SlpFile::read_shape_headers(&mut io::Cursor::new(data)));
+        for (shape_index, shape_header) in shape_headers.iter().enumerate() {
+            try!(shape_header.check_tables_within(shape_index, len));
+        }
+
+        let options = DecodeOptions::default();
+        let shapes: Result<Vec<SlpLogicalShape>> = shape_headers.into_par_iter()
+            .enumerate()
+            .map(|(shape_index, shape_header)| {
+                let mut shape = SlpLogicalShape::new();
+                shape.header = shape_header;
+                try!(SlpFile::read_pixel_data(&mut io::Cursor::new(data),
+                                              &mut shape,
+                                              shape_index,
+                                              player_index,
+                                              &options,
+                                              len,
+                                              &mut None));
+                Ok(shape)
+            })
+            .collect();
+
+        let mut slp_file = SlpFile::new();
+        slp_file.header = header;
+        slp_file.shapes = try!(shapes);
+        Ok(slp_file)
+    }
+
+    /// Like [read_from](#method.read_from), for streams whose length is already known.
+    ///
+    /// Every shape's outline and row offset tables are checked against `len` before any of
+    /// them are read, so a truncated or corrupt file fails early with `InvalidSlp`.
+    pub fn read_from_with_len<R: Read + Seek>(cursor: &mut R,
+                                              len: u64,
+                                              player_index: u8)
+                                              -> Result<SlpFile> {
+        SlpFile::read_shapes(cursor, player_index, &DecodeOptions::default(), Some(len), &mut None)
+    }
+
+    /// Like [read_from](#method.read_from), but with control over how decoding is done.
+    pub fn read_from_with_options<R: Read + Seek>(cursor: &mut R,
+                                                  player_index: u8,
+                                                  options: &DecodeOptions)
+                                                  -> Result<SlpFile> {
+        SlpFile::read_shapes(cursor, player_index, options, None, &mut None)
+    }
+
+    /// Like [read_from](#method.read_from), but gives up with `ErrorKind::Aborted` once `abort`
+    /// is set, e.g. by another thread.
+    ///
+    /// `abort` is checked before each shape is decoded.
+    pub fn read_from_cancelable<R: Read + Seek>(cursor: &mut R,
+                                                player_index: u8,
+                                                abort: &AtomicBool)
+                                                -> Result<SlpFile> {
+        if abort.load(Ordering::Relaxed) {
+            return Err(ErrorKind::Aborted.into());
+        }
+        let mut slp_file = SlpFile::new();
+        slp_file.header = {
+            let shapes = &mut slp_file.shapes;
+            try!(SlpFile::decode_each_shape(cursor,
+                                            player_index,
+                                            &DecodeOptions::default(),
+                                            None,
+                                            &mut None,
+                                            |_, shape| {
+                shapes.push(shape);
+                if abort.load(Ordering::Relaxed) {
+                    return Err(ErrorKind::Aborted.into());
+                }
+                Ok(())
+            }))
+        };
+        Ok(slp_file)
+    }
+
+    /// Like [read_from_with_options](#method.read_from_with_options), but writes a line to
+    /// `trace` for every command decoded.
+    ///
+    /// This is meant for debugging files with an unusual command stream.
+    pub fn read_from_with_trace<R: Read + Seek, W: Write>(cursor: &mut R,
+                                                          player_index: u8,
+                                                          options: &DecodeOptions,
+                                                          trace: &mut W)
+                                                          -> Result<SlpFile> {
+        SlpFile::read_shapes(cursor, player_index, options, None, &mut Some(trace))
+    }
+
+    fn read_shapes<R: Read + Seek>(cursor: &mut R,
+                                   player_index: u8,
+                                   options: &DecodeOptions,
+                                   len: Option<u64>,
+                                   trace: &mut Option<&mut dyn Write>)
+                                   -> Result<SlpFile> {
+        let mut slp_file = SlpFile::new();
+        slp_file.header = {
+            let shapes = &mut slp_file.shapes;
+            try!(SlpFile::decode_each_shape(cursor,
+                                                 player_index,
+                                                 options,
+                                                 len,
+                                                 trace,
+                                                 |_, shape| {
+                                                     shapes.push(shape);
+                                                     Ok(())
+                                                 }))
+        };
+        Ok(slp_file)
+    }
+
+    /// Reads only the header of shape `n`, seeking straight to it rather than reading the ones
+    /// before it.
+    ///
+    /// `cursor` must be at the start of the file.
+    pub fn read_nth_header<R: Read + Seek>(cursor: &mut R, n: usize) -> Result<SlpShapeHeader> {
+        let header = try!(SlpHeader::read_from(cursor));
+        SlpFile::read_nth_header_after(cursor, &header, n)
+    }
+
+    /// Like `read_nth_header`, for when the file header has already been read into `header`.
+    fn read_nth_header_after<R: Read + Seek>(cursor: &mut R,
+                                             header: &SlpHeader,
+                                             n: usize)
+                                             -> Result<SlpShapeHeader> {
+        if n >= header.shape_count as usize {
+            return Err(ErrorKind::InvalidSlp(format!("no shape {}; there are only {}",
+                                                     n,
+                                                     header.shape_count))
+                .into());
+        }
+        try!(cursor.seek(SeekFrom::Start(header.shape_table_offset() +
+                                         SHAPE_HEADER_SIZE * n as u64)));
+        SlpShapeHeader::read_from_file(cursor)
+    }
+
+    /// Decodes only shape `shape_index`, skipping over the others.
+    ///
+    /// `cursor` must be at the start of the file. Version 4 files fail with
+    /// `UnsupportedVersion`, like they do with [read_from](#method.read_from).
+    pub fn read_shape_from<R: Read + Seek>(cursor: &mut R,
+                                           shape_index: usize,
+                                           player_index: u8)
+                                           -> Result<SlpLogicalShape> {
+        let header = try!(SlpHeader::read_from(cursor));
+        try!(header.check_decodable());
+        let mut shape = SlpLogicalShape::new();
+        shape.header = try!(SlpFile::read_nth_header_after(cursor, &header, shape_index));
+        let len = try!(stream_len(cursor));
+        try!(shape.header.check_tables_within(shape_index, len));
+        try!(SlpFile::read_pixel_data(cursor,
+                                      &mut shape,
+                                      shape_index,
+                                      player_index,
+                                      &DecodeOptions::default(),
+                                      len,
+                                      &mut None));
+        Ok(shape)
+    }
+
+    /// Checks that the SLP in `cursor` decodes, returning the first error found.
+    ///
+    /// Every command is walked and checked just as when decoding, but no pixel buffers are
+    /// allocated, so this is much cheaper for checking lots of files.
+    ///
+    /// `cursor` must be at the start of the file.
+    pub fn validate<R: Read + Seek>(cursor: &mut R, player_index: u8) -> Result<()> {
+        let options = DecodeOptions::default();
+        let (_, shape_headers) = try!(SlpFile::read_shape_headers(cursor));
+        let len = try!(stream_len(cursor));
+        for (shape_index, shape_header) in shape_headers.iter().enumerate() {
+            try!(shape_header.check_tables_within(shape_index, len));
+        }
+
+        for (shape_index, shape_header) in shape_headers.into_iter().enumerate() {
+            if shape_header.is_zero_area() {
+                continue;
+            }
+            try!(check_dimensions(&shape_header, &options));
+            let row_starts = try!(SlpFile::read_row_starts(cursor,
+                                                           &shape_header,
+                                                           shape_index,
+                                                           &options,
+                                                           len));
+            let mut shape = SlpLogicalShape::new();
+            shape.header = shape_header;
+            let mut context = DecodeContext {
+                player_index: player_index,
+                options: &options,
+                len: len,
+                trace: &mut None,
+            };
+            try!(SlpFile::walk_rows(cursor, &mut shape, shape_index, &row_starts, &mut context));
+        }
+        Ok(())
+    }
+
+    /// Reads the file header and every shape header, without decoding any pixels.
+    pub(crate) fn read_shape_headers<R: Read + Seek>(cursor: &mut R)
+                                                     -> Result<(SlpHeader, Vec<SlpShapeHeader>)> {
+        let header = try!(SlpHeader::read_from(cursor));
+        try!(header.check_decodable());
+
+        // Don't trust a corrupt count enough to read that many headers
+        let len = try!(stream_len(cursor));
+        let table_end = SLP_HEADER_SIZE + SHAPE_HEADER_SIZE * header.shape_count as u64;
+        if table_end > len {
+            return Err(ErrorKind::InvalidSlp(format!("{} shape headers don't fit in a {} byte \
+                                                      file",
+                                                     header.shape_count,
+                                                     len))
+                .into());
+        }
+
+        let mut shape_headers = Vec::with_capacity(header.shape_count as usize);
+        for _shape_index in 0..header.shape_count {
+            shape_headers.push(try!(SlpShapeHeader::read_from_file(cursor)));
+        }
+        Ok((header, shape_headers))
+    }
+
+    /// Decodes the shapes in `cursor` one at a time, handing each one to `f` rather than
+    /// keeping them all around.
+    ///
+    /// The shape tables are validated against `len` before any are read. If it isn't given, the
+    /// stream's length is found by seeking to its end.
+    pub(crate) fn decode_each_shape<R, F>(cursor: &mut R,
+                                          player_index: u8,
+                                          options: &DecodeOptions,
+                                          len: Option<u64>,
+                                          trace: &mut Option<&mut dyn Write>,
+                                          mut f: F)
+                                          -> Result<SlpHeader>
+        where R: Read + Seek,
+              F: FnMut(usize, SlpLogicalShape) -> Result<()>
+    {
+        let (header, shape_headers) = try!(SlpFile::read_shape_headers(cursor));
+        let len = match len {
+            Some(len) => len,
+            None => try!(stream_len(cursor)),
+        };
+        for (shape_index, shape_header) in shape_headers.iter().enumerate() {
+            try!(shape_header.check_tables_within(shape_index, len));
+        }
+
+        // Everything the file is known to use, for spotting trailing data
+        let mut data_end = SLP_HEADER_SIZE + SHAPE_HEADER_SIZE * shape_headers.len() as u64;
+
+        for (shape_index, shape_header) in shape_headers.into_iter().enumerate() {
+            if let Some(ref mut trace) = *trace {
+                try!(writeln!(trace,
+                              "shape {}: {}x{}",
+                              shape_index,
+                              shape_header.width,
+                              shape_header.height));
+            }
+            let mut shape = SlpLogicalShape::new();
+            shape.header = shape_header;
+            let shape_end = try!(SlpFile::read_pixel_data(cursor,
+                                                          &mut shape,
+                                                          shape_index,
+                                                          player_index,
+                                                          options,
+                                                          len,
+                                                          trace));
+            data_end = cmp::max(data_end, shape_end);
+            try!(f(shape_index, shape));
+        }
+
+        if options.reject_trailing_data && data_end < len {
+            return Err(ErrorKind::InvalidSlp(format!("{} bytes of unexpected data follow the last \
+                                                      shape, which ends at offset {}",
+                                                     len - data_end,
+                                                     data_end))
+                .into());
+        }
+        Ok(header)
+    }
+
+    /// Decodes the first shape of the SLP in `cursor` and appends it to this file.
+    ///
+    /// This is handy for assembling an animation out of separate single-frame SLPs. Version 4
+    /// files fail with `UnsupportedVersion`.
+    pub fn push_decoded_frame<R: Read + Seek>(&mut self,
+                                              cursor: &mut R,
+                                              player_index: u8)
+                                              -> Result<()> {
+        let header = try!(SlpHeader::read_from(cursor));
+        try!(header.check_decodable());
+        if header.shape_count == 0 {
+            return Err(ErrorKind::InvalidSlp("no shapes to append".into()).into());
+        }
+
+        let mut shape = SlpLogicalShape::new();
+        shape.header = try!(SlpShapeHeader::read_from_file(cursor));
+        let shape_index = self.shapes.len();
+        let len = try!(stream_len(cursor));
+        try!(SlpFile::read_pixel_data(cursor,
+                                      &mut shape,
+                                      shape_index,
+                                      player_index,
+                                      &DecodeOptions::default(),
+                                      len,
+                                      &mut None));
+
+        self.shapes.push(shape);
+        self.header.shape_count += 1;
+        Ok(())
+    }
+
+    /// Decodes the pixels of `shape`, whose header has already been read, returning the offset
+    /// just past the last byte of its tables and commands.
+    fn read_pixel_data<R: Read + Seek>(cursor: &mut R,
+                                       shape: &mut SlpLogicalShape,
+                                       shape_index: usize,
+                                       player_index: u8,
+                                       options: &DecodeOptions,
+                                       len: u64,
+                                       trace: &mut Option<&mut dyn Write>)
+                                       -> Result<u64> {
+        try!(check_dimensions(&shape.header, options));
+        if shape.header.is_zero_area() {
+            // A shape zero pixels wide still has a row of commands for each of its rows
+            shape.commands = vec![Vec::new(); shape.header.height as usize];
+            return Ok(0);
+        }
+        let row_starts = try!(SlpFile::read_row_starts(cursor,
+                                                       &shape.header,
+                                                       shape_index,
+                                                       options,
+                                                       len));
+        let mut context = DecodeContext {
+            player_index: player_index,
+            options: options,
+            len: len,
+            trace: trace,
+        };
+        let (commands_end, commands_size) =
+            try!(SlpFile::decode_rows(cursor, shape, shape_index, &row_starts, &mut context));
+
+        let table_size = shape.header.height as u64 * size_of::<u32>() as u64;
+        // Rows are never read from before the end of the tables, so the data starts with them
+        let data_start = cmp::min(shape.header.shape_outline_offset,
+                                  shape.header.shape_data_offsets) as u64;
+        let data_end = cmp::max(commands_end,
+                                cmp::max(shape.header.shape_outline_offset as u64 + table_size,
+                                         shape.header.shape_data_offsets as u64 + table_size));
+
+        // Keep the shape's encoded data around for writing, but only if it's in one compact
+        // piece. Rows can point anywhere in the file, and copying everything between them
+        // would let every shape of a small file hold most of it. Shapes whose data is spread
+        // out are re-encoded instead.
+        let data_length = data_end - data_start;
+        if data_length > 2 * (2 * table_size + commands_size) {
+            return Ok(data_end);
+        }
+        // Read through `take` so a bogus table offset can't make us allocate more than the
+        // stream actually holds
+        let mut data = Vec::new();
+        try!(seek_checked(cursor, data_start, len));
+        try!(cursor.by_ref().take(data_length).read_to_end(&mut data));
+        if (data.len() as u64) < data_length {
+            return Err(ErrorKind::InvalidSlp("shape tables extend past the end of the file".into())
+                .into());
+        }
+        shape.raw = Some(SlpRawShape {
+            offset: data_start as u32,
+            data: data,
+            content_hash: shape.content_hash(),
+        });
+        Ok(data_end)
+    }
+
+    /// Reads where each row of the shape described by `header` starts, with `None` for
+    /// transparent rows.
+    pub(crate) fn read_row_starts<R: Read + Seek>(cursor: &mut R,
+                                                  header: &SlpShapeHeader,
+                                                  shape_index: usize,
+                                                  options: &DecodeOptions,
+                                                  len: u64)
+                                                  -> Result<Vec<Option<RowStart>>> {
+        let mut row_starts = Vec::with_capacity(header.height as usize);
+        for y in 0..header.height {
+            match SlpFile::read_row_start(cursor, header, y, options, len) {
+                Ok(start) => row_starts.push(start),
+                Err(err) => return row_error(err, shape_index, y),
+            }
+        }
+        Ok(row_starts)
+    }
+
+    /// Returns, for each row of shape `index`, the offset of its first command and how many
+    /// transparent pixels pad it on the left and right, so rows can be decoded one at a time.
+    ///
+    /// `cursor` must hold the file this was read from. Transparent rows have no commands, and
+    /// are returned as a start of 0 with the whole width as left padding. Zero-area shapes have
+    /// no rows to index, and their tables aren't read.
+    pub fn index_frame_rows<R: Read + Seek>(&self,
+                                            cursor: &mut R,
+                                            index: usize)
+                                            -> Result<Vec<(u64, u32, u32)>> {
+        let header = match self.shapes.get(index) {
+            Some(shape) => &shape.header,
+            None => {
+                return Err(ErrorKind::InvalidSlp(format!("no shape {}; there are only {}",
+                                                         index,
+                                                         self.shapes.len()))
+                    .into())
+            }
+        };
+        if header.is_zero_area() {
+            return Ok(Vec::new());
+        }
+        let options = DecodeOptions::default();
+        let len = try!(stream_len(cursor));
+        let row_starts = try!(SlpFile::read_row_starts(cursor, header, index, &options, len));
+        Ok(row_starts.iter()
+            .map(|start| match *start {
+                Some(start) => (start.offset as u64, start.left, start.right_padding),
+                None => (0, header.width, 0),
+            })
+            .collect())
+    }
+
+    fn read_row_start<R: Read + Seek>(cursor: &mut R,
+                                      header: &SlpShapeHeader,
+                                      y: u32,
+                                      options: &DecodeOptions,
+                                      len: u64)
+                                      -> Result<Option<RowStart>> {
+        let line_outline_offset = try!(table_entry_offset(header.shape_outline_offset, y));
+        try!(seek_checked(cursor, line_outline_offset as u64, len));
+        let left = try!(cursor.read_u16()) as u32;
+        let right_padding = try!(cursor.read_u16()) as u32;
+        // The marker goes in both halves of the pair. Only one of them having it means the
+        // table is corrupt, not that the other half is a real offset
+        let transparent_row = options.transparent_row as u32;
+        match (left == transparent_row, right_padding == transparent_row) {
+            // Fully transparent; skip to next line
+            (true, true) => return Ok(None),
+            (false, false) => {}
+            _ => {
+                return Err(ErrorKind::InvalidSlp(format!("row {} has a malformed outline pair \
+                                                          ({:#06x}, {:#06x}); only one half \
+                                                          marks it transparent",
+                                                         y,
+                                                         left,
+                                                         right_padding))
+                    .into())
+            }
+        }
+        if left + right_padding > header.width {
+            return Err(ErrorKind::InvalidSlp(format!("row {}'s outline pair ({}, {}) doesn't fit \
+                                                      in {} pixels",
+                                                     y,
+                                                     left,
+                                                     right_padding,
+                                                     header.width))
+                .into());
+        }
+
+        // The shape_data_offset points to an array of offsets to actual pixel data
+        // Seek out the offset for the current Y coordinate
+        let shape_data_ptr_offset = try!(table_entry_offset(header.shape_data_offsets, y));
+        try!(seek_checked(cursor, shape_data_ptr_offset as u64, len));
+        let data_offset = try!(cursor.read_u32());
+
+        // Commands follow both tables; pointing into them would decode table bytes as commands
+        let table_size = header.height as u64 * size_of::<u32>() as u64;
+        let commands_start = cmp::max(header.shape_outline_offset as u64 + table_size,
+                                      header.shape_data_offsets as u64 + table_size);
+        if (data_offset as u64) < commands_start {
+            return Err(ErrorKind::InvalidSlp(format!("row {}'s commands at offset {} start before \
+                                                      the command section at {}",
+                                                     y,
+                                                     data_offset,
+                                                     commands_start))
+                .into());
+        }
+        Ok(Some(RowStart {
+            left: left,
+            right_padding: right_padding,
+            offset: data_offset,
+        }))
+    }
+
+    /// Decodes the commands of every row of `shape` into its buffers, returning the furthest
+    /// offset any row's commands reached and how many command bytes were read in all.
+    pub(crate) fn decode_rows<R: Read + Seek>(cursor: &mut R,
+                                              shape: &mut SlpLogicalShape,
+                                              shape_index: usize,
+                                              row_starts: &[Option<RowStart>],
+                                              context: &mut DecodeContext)
+                                              -> Result<(u64, u64)> {
+        // Reserve and zero out pixel data
+        let area = match (shape.header.width as usize).checked_mul(shape.header.height as usize) {
+            Some(area) => area,
+            None => {
+                return Err(ErrorKind::InvalidSlp(format!("shape {} is too large at {}x{}",
+                                                         shape_index,
+                                                         shape.header.width,
+                                                         shape.header.height))
+                    .into())
+            }
+        };
+        shape.pixels.resize(area, 0u8);
+        let transparent_row = vec![DrawCommand::Skip { count: shape.header.width }];
+        shape.commands.resize(shape.header.height as usize, transparent_row);
+        SlpFile::walk_rows(cursor, shape, shape_index, row_starts, context)
+    }
+
+    /// Decodes the commands of every row of `shape`, returning the furthest offset any row's
+    /// commands reached and how many command bytes were read in all.
+    ///
+    /// Pixels are only stored if `shape`'s buffers have already been sized to fit them.
+    fn walk_rows<R: Read + Seek>(cursor: &mut R,
+                                 shape: &mut SlpLogicalShape,
+                                 shape_index: usize,
+                                 row_starts: &[Option<RowStart>],
+                                 context: &mut DecodeContext)
+                                 -> Result<(u64, u64)> {
+        shape.resolved_row_offsets = vec![0u32; shape.header.height as usize];
+        shape.transparent_rows = row_starts.iter().map(Option::is_none).collect();
+        // Transparent rows only get this far with the marker in both halves of their pair
+        let marker = context.options.transparent_row;
+        shape.outline = row_starts.iter()
+            .map(|start| match *start {
+                Some(ref start) => (start.left as u16, start.right_padding as u16),
+                None => (marker, marker),
+            })
+            .collect();
+
+        let mut commands_end = 0;
+        let mut commands_size = 0;
+        for (y, start) in row_starts.iter().enumerate() {
+            let y = y as u32;
+            if let Some(ref start) = *start {
+                let row = SlpFile::read_row(cursor, shape, y, start, context);
+                match row {
+                    Ok(row_end) => {
+                        commands_end = cmp::max(commands_end, row_end);
+                        commands_size += row_end - start.offset as u64;
+                    }
+                    Err(err) => return row_error(err, shape_index, y),
+                }
+            }
+        }
+        Ok((commands_end, commands_size))
+    }
+
+    /// Decodes the commands of row `y` of `shape`, which begin at `start`, returning where
+    /// they ended.
+    fn read_row<R: Read + Seek>(cursor: &mut R,
+                                shape: &mut SlpLogicalShape,
+                                y: u32,
+                                start: &RowStart,
+                                context: &mut DecodeContext)
+                                -> Result<u64> {
+        let (player_index, options, len) = (context.player_index, context.options, context.len);
+        let width = shape.header.width;
+        let layout = options.layout;
+        let mut x = start.left;
+        let right_padding = start.right_padding;
+
+        try!(seek_checked(cursor, start.offset as u64, len));
+        shape.resolved_row_offsets[y as usize] = start.offset;
+
+        let mut row = Vec::new();
+        push_command(&mut row, DrawCommand::Skip { count: x });
+
+        // Zero length commands don't advance x, so a corrupt row could otherwise run on for as
+        // long as the file does
+        let max_commands = width as u64 * 2 + 16;
+        let mut command_count = 0u64;
+        loop {
+            command_count += 1;
+            if command_count > max_commands {
+                return Err(ErrorKind::InvalidSlp(format!("row {} has more than {} commands",
+                                                         y,
+                                                         max_commands))
+                    .into());
+            }
+            let cmd_byte = try!(cursor.read_u8());
+
+            // End of line indicator
+            if is_end_of_line(cmd_byte, options) {
+                if let Some(ref mut trace) = *context.trace {
+                    try!(writeln!(trace, "row {}, x {}: end of line", y, x));
+                }
+                let expected = width - right_padding;
+                let tolerated = options.lenient_row_end && x == expected + 1 && x <= width;
+                if x != expected && !tolerated {
+                    return Err(ErrorKind::InvalidSlp(format!("Line {} not the expected \
+                                                              size. Was {} but should be {}",
+                                                             y,
+                                                             x,
+                                                             width - right_padding))
+                        .into());
+                }
+                if !shape.commands.is_empty() {
+                    push_command(&mut row, DrawCommand::Skip { count: width - x });
+                    shape.commands[y as usize] = row;
+                }
+                return Ok(try!(cursor.stream_position()));
+            }
+
+            if x > width {
+                return Err(ErrorKind::InvalidSlp("Unexpected error occurred.
+                    Line length already exceeded before stop."
+                        .into())
+                    .into());
+            }
+
+            use self::SlpEncodedLength::*;
+
+            // SLP opcodes are stored in the lowest 4 bits of the command byte.
+            let opcode = cmd_byte & 0b00001111;
+            let start_x = x;
+
+            let command = match opcode {
+                // Block copy
+                0b0000 |
+                0b0100 |
+                0b1000 |
+                0b1100 => {
+                    let length = try!(SixUpperBit.decode(cmd_byte, x, y, cursor));
+                    try!(check_run(x, length as u32, width, y));
+                    let mut indices = vec![0u8; length];
+                    try!(cursor.read_exact(&mut indices));
+                    put_run(shape, layout, x, y, indices);
+                    DrawCommand::Color { count: length as u32 }
+                }
+
+                // Skip pixels
+                0b0001 |
+                0b0101 |
+                0b1001 |
+                0b1101 => {
+                    let length = try!(SixUpperBit.decode(cmd_byte, x, y, cursor));
+                    DrawCommand::Skip { count: length as u32 }
+                }
+
+                // Large block copy
+                0b0010 => {
+                    let length = try!(LargeLength.decode(cmd_byte, x, y, cursor));
+                    try!(check_run(x, length as u32, width, y));
+                    let mut indices = vec![0u8; length];
+                    try!(cursor.read_exact(&mut indices));
+                    put_run(shape, layout, x, y, indices);
+                    DrawCommand::Color { count: length as u32 }
+                }
+
+                // Large skip pixels
+                0b0011 => {
+                    let length = try!(LargeLength.decode(cmd_byte, x, y, cursor));
+                    DrawCommand::Skip { count: length as u32 }
+                }
+
+                // Copy and colorize block
+                0b0110 => {
+                    let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                    try!(check_run(x, length as u32, width, y));
+                    let mut relative = vec![0u8; length];
+                    try!(cursor.read_exact(&mut relative));
+                    DrawCommand::PlayerColor { relative: relative }
+                }
+
+                // Fill block
+                0b0111 => {
+                    let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                    try!(check_run(x, length as u32, width, y));
+                    let index = try!(cursor.read_u8());
+                    put_run(shape, layout, x, y, iter::repeat(index).take(length));
+                    DrawCommand::Fill { count: length as u32 }
+                }
+
+                // Transform block
+                0b1010 => {
+                    let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                    try!(check_run(x, length as u32, width, y));
+                    let relative_index = try!(cursor.read_u8());
+                    DrawCommand::PlayerColor { relative: vec![relative_index; length] }
+                }
+
+                // Shadow pixels
+                0b1011 => {
+                    let length = try!(FourUpperBit.decode(cmd_byte, x, y, cursor));
+                    try!(check_run(x, length as u32, width, y));
+                    // The length is determined as in cases 6, 7 and 0x0a. For the length
+                    // of the run, the destination pixels already in the buffer are used
+                    // as a lookup into a "shadow table" and this lookup pixel is then
+                    // used to draw into the buffer. The shadow table is typically a
+                    // color-tinted variation of the real color table, and is generally
+                    // used to draw things like the red-tinted checkerboard sprites when
+                    // you try to place a building in an area where it cannot be placed.
+                    //
+                    // We only record where the shadow is; the darkening itself happens
+                    // when the shape is converted to RGBA.
+                    DrawCommand::Shadow { count: length as u32 }
+                }
+
+                // Extended
+                0b1110 => {
+                    // The extended opcode lives in the top 4 bits of the command byte (yes, I lied above).
+                    let command = match cmd_byte >> 4 {
+                        // Render hints and palette transforms don't draw anything, so they're
+                        // kept as they are for writing
+                        0x0..=0x3 => {
+                            shape.raw_commands.push(RawCommand {
+                                x: x,
+                                y: y,
+                                length: 0,
+                                bytes: vec![cmd_byte],
+                            });
+                            DrawCommand::Skip { count: 0 }
+                        }
+                        // Single outline pixels
+                        0x4 => DrawCommand::Outline { count: 1 },
+                        0x6 => DrawCommand::BlackOutline { count: 1 },
+                        // Outline spans
+                        0x5 => DrawCommand::Outline { count: try!(cursor.read_u8()) as u32 },
+                        0x7 => DrawCommand::BlackOutline { count: try!(cursor.read_u8()) as u32 },
+                        _ => {
+                            return Err(ErrorKind::InvalidSlp(format!("unknown command byte \
+                                                                      {:#04x} at row {}, x {}",
+                                                                     cmd_byte,
+                                                                     y,
+                                                                     x))
+                                .into())
+                        }
+                    };
+                    try!(check_run(x, command.len(), width, y));
+                    command
+                }
+
+                // Only reachable with a nonstandard end of line marker
+                _ => {
+                    return Err(ErrorKind::InvalidSlp(format!("unknown command byte {:#04x} at \
+                                                              row {}, x {}",
+                                                             cmd_byte,
+                                                             y,
+                                                             x))
+                        .into())
+                }
+            };
+
+            // Player colors are resolved into `pixels` from their relative indices
+            if let DrawCommand::PlayerColor { ref relative } = command {
+                let base = options.player_color_base;
+                put_run(shape,
+                        layout,
+                        x,
+                        y,
+                        relative.iter().map(|&index| remap_index(player_index, index, base)));
+            }
+            x += command.len();
+            push_command(&mut row, command);
+
+            if let Some(ref mut trace) = *context.trace {
+                try!(writeln!(trace,
+                              "row {}, x {}: {} ({:#04x}), {} pixels",
+                              y,
+                              start_x,
+                              command_name(cmd_byte),
+                              cmd_byte,
+                              x - start_x));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DrawCommand, PixelKind, RawCommand, SlpExtendedHeader, SlpFile, SlpHeader,
+                SlpLogicalShape, SlpShapeHeader, SlpVersion, Error, ErrorKind, Result, NO_PLAYER,
+                decode_frame_from_slice};
+    use options::DecodeOptions;
+    use std::io;
+    use std::io::prelude::*;
+    use test_util::{FixtureRow, build_slp, push_u16, push_u32, set_pixel_kinds, single_shape_slp};
+
+    #[test]
+    fn test_slp_header_read_from() {
         use std::io;
         let data = "2.0N\x04\0\0\0test\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".as_bytes();
         let result = SlpHeader::read_from(&mut io::Cursor::new(data));
@@ -384,19 +2352,1382 @@ mod tests {
         }
     }
 
+    /// Builds the headers of a `4.0X` file with a 3x4 and a 5x6 shape, whose shape table starts
+    /// at `table_offset`.
+    fn version_4_slp(table_offset: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"4.0X");
+        push_u16(&mut data, 2); // Frames
+        push_u16(&mut data, 8); // Frame type
+        push_u16(&mut data, 5); // Directions
+        push_u16(&mut data, 1); // Frames per direction
+        push_u32(&mut data, 55);
+        push_u32(&mut data, table_offset); // Main shape table
+        push_u32(&mut data, 0);
+        data.extend_from_slice(&[0u8; 8]);
+        data.resize(table_offset as usize, 0);
+        for &(width, height) in &[(3, 4), (5, 6)] {
+            data.extend_from_slice(&[0u8; 16]);
+            push_u32(&mut data, width);
+            push_u32(&mut data, height);
+            data.extend_from_slice(&[0u8; 8]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_slp_header_read_from_version_4() {
+        let data = version_4_slp(32);
+        let header = SlpHeader::read_from(&mut io::Cursor::new(&data)).unwrap();
+        assert_eq!(2, header.shape_count);
+        assert_eq!(Some(SlpVersion { major: 4, minor: 0, suffix: b'X' }), header.version());
+        assert_eq!(Some(SlpExtendedHeader {
+                       frame_type: 8,
+                       num_directions: 5,
+                       frames_per_direction: 1,
+                       palette_id: 55,
+                       offset_main: 32,
+                       offset_secondary: 0,
+                   }),
+                   header.extended);
+        assert_eq!("", header.comment_str());
+
+        let shape_header = SlpFile::read_nth_header(&mut io::Cursor::new(&data), 1).unwrap();
+        assert_eq!((5, 6), (shape_header.width, shape_header.height));
+
+        match SlpFile::read_from(&mut io::Cursor::new(&data)) {
+            Err(Error(ErrorKind::UnsupportedVersion(version), _)) => assert_eq!("4.0X", version),
+            other => panic!("expected an unsupported version, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_header_read_from_bad_header() {
+        use std::io;
+        let data = "2.1N\x04\0\0\0test\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".as_bytes();
+        let result = SlpHeader::read_from(&mut io::Cursor::new(data));
+        match result {
+            Ok(_) => panic!("expected bad header error"),
+            Err(e) => {
+                match e.kind() {
+                    &ErrorKind::InvalidSlp(ref reason) => assert_eq!(*reason, "bad header".to_string()),
+                    _ => panic!("unexpected error: {}", e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_slp_header_version() {
+        let mut header = SlpHeader::new();
+        assert_eq!("", header.version_str());
+        assert_eq!(None, header.version());
+
+        header.file_version = *b"2.0N";
+        assert_eq!("2.0N", header.version_str());
+        assert_eq!(Some(SlpVersion { major: 2, minor: 0, suffix: b'N' }), header.version());
+
+        header.file_version = *b"2.0\0";
+        assert_eq!("2.0", header.version_str());
+        assert_eq!(Some(SlpVersion { major: 2, minor: 0, suffix: 0 }), header.version());
+    }
+
+    #[test]
+    fn test_slp_header_with_version_and_comment() {
+        let header = SlpHeader::with_version_and_comment("2.0N", "made by test").unwrap();
+        assert_eq!(*b"2.0N", header.file_version);
+        assert_eq!(0, header.shape_count);
+        assert_eq!("made by test", header.comment_str());
+
+        let header = SlpHeader::with_version_and_comment("2.0", "").unwrap();
+        assert_eq!(*b"2.0\0", header.file_version);
+
+        // Comments are stored as Latin-1, one byte per character
+        let header = SlpHeader::with_version_and_comment("2.0N", "\u{e9}t\u{e9}").unwrap();
+        assert_eq!([0xE9, b't', 0xE9, 0], header.comment[..4]);
+
+        let too_long = "a comment over 24 bytes long";
+        for &(version, comment) in &[("2.0NN", ""), ("", ""), ("N2.0", ""), ("2.0N", too_long),
+                                     ("4.0X", "x"), ("2.0N", "\u{100}")] {
+            match SlpHeader::with_version_and_comment(version, comment) {
+                Err(Error(ErrorKind::InvalidHeader(_), _)) => {}
+                other => panic!("{:?} {:?}: {:?}", version, comment, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_slp_logical_shape_looks_like_mask() {
+        use super::PixelKind::*;
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 4;
+        shape.header.height = 1;
+        shape.pixels = vec![0; 4];
+        set_pixel_kinds(&mut shape, &[Skip; 4]);
+        assert!(!shape.looks_like_mask());
+
+        set_pixel_kinds(&mut shape, &[Shadow, Shadow, Skip, Skip]);
+        assert!(shape.looks_like_mask());
+
+        shape.pixels = vec![0xF0, 0xF4, 0xFA, 0xFF];
+        set_pixel_kinds(&mut shape, &[Color; 4]);
+        assert!(shape.looks_like_mask());
+
+        shape.pixels[0] = 0x20;
+        set_pixel_kinds(&mut shape, &[Color; 4]);
+        assert!(!shape.looks_like_mask());
+
+        shape.pixels[0] = 0xF0;
+        set_pixel_kinds(&mut shape, &[Remap, Color, Color, Color]);
+        assert!(!shape.looks_like_mask());
+    }
+
+    #[test]
+    fn test_slp_file_read_nth_header() {
+        let data = build_slp(&[(3, &[(0, 0, &[0x04, 7, 0x0F])]),
+                               (5, &[(0x8000, 0x8000, &[]), (0x8000, 0x8000, &[])])]);
+        let header = SlpFile::read_nth_header(&mut io::Cursor::new(&data), 1).unwrap();
+        assert_eq!((5, 2), (header.width, header.height));
+        assert!(SlpFile::read_nth_header(&mut io::Cursor::new(&data), 2).is_err());
+    }
+
+    #[test]
+    fn test_slp_file_autodetect() {
+        let mut data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        let slp_file = SlpFile::autodetect(&mut io::Cursor::new(&data), 1).unwrap();
+        assert_eq!(vec![7], slp_file.shapes[0].pixels);
+
+        data[..4].copy_from_slice(b"4.0X");
+        match SlpFile::autodetect(&mut io::Cursor::new(&data), 1) {
+            Err(Error(ErrorKind::UnsupportedVersion(version), _)) => assert_eq!("4.0X", version),
+            other => panic!("expected an unsupported version, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_version_4_pixel_data_is_unsupported() {
+        fn assert_unsupported<T>(result: Result<T>, expected: &str) {
+            match result {
+                Err(Error(ErrorKind::UnsupportedVersion(version), _)) => {
+                    assert_eq!(expected, version)
+                }
+                Err(err) => panic!("expected an unsupported version, got {}", err),
+                Ok(_) => panic!("expected an unsupported version"),
+            }
+        }
+
+        // Headers honor the shape table offset
+        let mut data = version_4_slp(48);
+        let shape_header = SlpFile::read_nth_header(&mut io::Cursor::new(&data), 1).unwrap();
+        assert_eq!((5, 6), (shape_header.width, shape_header.height));
+
+        assert_unsupported(SlpFile::read_from(&mut io::Cursor::new(&data)), "4.0X");
+        assert_unsupported(SlpFile::validate(&mut io::Cursor::new(&data), 1), "4.0X");
+        assert_unsupported(SlpFile::read_shape_from(&mut io::Cursor::new(&data), 1, 1),
+                           "4.0X");
+        assert_unsupported(decode_frame_from_slice(&data), "4.0X");
+        let mut slp_file = SlpFile::new();
+        assert_unsupported(slp_file.push_decoded_frame(&mut io::Cursor::new(&data), 1), "4.0X");
+        assert!(slp_file.shapes.is_empty());
+        assert_unsupported(SlpFile::autodetect(&mut io::Cursor::new(&data), 1), "4.0X");
+
+        data[..4].copy_from_slice(b"4.0N");
+        assert_unsupported(SlpFile::autodetect(&mut io::Cursor::new(&data), 1), "4.0N");
+        assert_unsupported(decode_frame_from_slice(&data), "4.0N");
+    }
+
+    #[test]
+    fn test_slp_file_read_shape_from() {
+        let data = build_slp(&[(1, &[(0, 0, &[0x04, 7, 0x0F])]),
+                               (2, &[(0x8000, 0x8000, &[]), (0, 0, &[0x08, 8, 9, 0x0F])])]);
+        let shape = SlpFile::read_shape_from(&mut io::Cursor::new(&data), 1, 1u8).unwrap();
+        assert_eq!((2, 2), (shape.header.width, shape.header.height));
+        assert_eq!(vec![0, 0, 8, 9], shape.pixels);
+
+        let all = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        assert_eq!(all.shapes[1].content_hash(), shape.content_hash());
+
+        match SlpFile::read_shape_from(&mut io::Cursor::new(&data), 2, 1u8) {
+            Err(Error(ErrorKind::InvalidSlp(_), _)) => {}
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_iteration() {
+        let data = build_slp(&[(1, &[(0, 0, &[0x04, 7, 0x0F])]),
+                               (2, &[(0, 0, &[0x08, 8, 9, 0x0F])])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        assert_eq!(2, slp_file.shape_count());
+        let widths: Vec<u32> = (&slp_file).into_iter().map(|shape| shape.header.width).collect();
+        assert_eq!(vec![1, 2], widths);
+        let mut count = 0;
+        for shape in &slp_file {
+            assert_eq!(1, shape.header.height);
+            count += 1;
+        }
+        assert_eq!(2, count);
+        assert_eq!(2, slp_file.shapes_iter().count());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_absurd_shape_count() {
+        let mut data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        data[4..8].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0x7F]);
+        match SlpFile::read_from(&mut io::Cursor::new(&data)) {
+            Err(Error(ErrorKind::InvalidSlp(reason), _)) => {
+                assert!(reason.starts_with("2147483647 shape headers don't fit"), "{}", reason)
+            }
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_bytes() {
+        let data = single_shape_slp(2, &[(0, 0, &[0x04, 7, 0x16, 3, 0x0F])]);
+        let slp_file = SlpFile::read_from_bytes(&data, 2u8).unwrap();
+        assert_eq!(vec![7, 32 + 3], slp_file.shapes[0].pixels);
+        assert!(SlpFile::read_from_bytes(&data[..data.len() - 1], 2u8).is_err());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_alpha_mask() {
+        use super::PixelKind::*;
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 3;
+        shape.header.height = 3;
+        shape.pixels = vec![0; 9];
+        set_pixel_kinds(&mut shape, &[Shadow, Skip, Skip, Skip, Color, Remap, Skip, Outline, Skip]);
+        assert_eq!(vec![false, false, false, false, true, true, false, false, false],
+                   shape.alpha_mask());
+        assert_eq!(Some((1, 1, 3, 2)), shape.opaque_bounds());
+
+        set_pixel_kinds(&mut shape, &[Skip, Shadow, Skip, Skip, Skip, Skip, Skip, Skip, Skip]);
+        assert_eq!(None, shape.opaque_bounds());
+        assert!(shape.bounds_relative_to_center().is_some());
+    }
+
+    #[test]
+    fn test_slp_header_comment_str() {
+        let mut header = SlpHeader::new();
+        header.file_version = *b"2.0N";
+        header.comment[..4].copy_from_slice(b"test");
+        assert_eq!("test", header.comment_str());
+
+        header.comment = [0u8; 24];
+        header.comment[..12].copy_from_slice(b"genie 2.7   ");
+        assert_eq!("genie 2.7", header.comment_str());
+
+        header.comment[..5].copy_from_slice(b"\x07caf\xE9");
+        assert_eq!("caf\u{e9} 2.7", header.comment_str());
+
+        header.file_version = *b"4.0X";
+        assert_eq!("", header.comment_str());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_records_commands() {
+        use super::DrawCommand::*;
+        let data = single_shape_slp(6,
+                                    &[(1, 1, &[0x08, 7, 8, 0x1B, 0x17, 9, 0x0F]),
+                                      (0x8000, 0x8000, &[])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        let shape = &slp_file.shapes[0];
+        assert_eq!(vec![0, 7, 8, 0, 9, 0, 0, 0, 0, 0, 0, 0], shape.pixels);
+        assert_eq!(vec![vec![Skip { count: 1 },
+                             Color { count: 2 },
+                             Shadow { count: 1 },
+                             Fill { count: 1 },
+                             Skip { count: 1 }],
+                        vec![Skip { count: 6 }]],
+                   shape.commands);
+    }
+
+    #[test]
+    fn test_slp_logical_shape_enumerate_pixels() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 2;
+        shape.header.height = 2;
+        shape.pixels = vec![0, 1, 2, 0];
+        set_pixel_kinds(&mut shape,
+                        &[PixelKind::Skip, PixelKind::Color, PixelKind::Remap, PixelKind::Skip]);
+
+        let drawn: Vec<_> = shape.enumerate_pixels()
+            .filter(|&(_, _, kind, _)| kind != PixelKind::Skip)
+            .map(|(x, y, _, index)| (x, y, index))
+            .collect();
+        assert_eq!(vec![(1, 0, 1), (0, 1, 2)], drawn);
+    }
+
+    #[test]
+    fn test_slp_file_push_decoded_frame() {
+        let first = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        let second = single_shape_slp(2, &[(0, 0, &[0x08, 8, 9, 0x0F])]);
+
+        let mut slp_file = SlpFile::read_from(&mut io::Cursor::new(first)).unwrap();
+        slp_file.push_decoded_frame(&mut io::Cursor::new(second), 1u8).unwrap();
+        assert_eq!(2, slp_file.header.shape_count);
+        assert_eq!(2, slp_file.shapes.len());
+        assert_eq!(vec![8, 9], slp_file.shapes[1].pixels);
+
+        let empty = "2.0N\0\0\0\0test\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".as_bytes();
+        assert!(slp_file.push_decoded_frame(&mut io::Cursor::new(empty), 1u8).is_err());
+        assert_eq!(2, slp_file.header.shape_count);
+    }
+
+    #[test]
+    fn test_slp_file_read_from_bad_length_context() {
+        let data = single_shape_slp(4, &[(0, 3, &[0x04, 7, 0x0F]), (1, 0, &[0x00, 0x0F])]);
+        match SlpFile::read_from(&mut io::Cursor::new(data)) {
+            Ok(_) => panic!("expected bad length error"),
+            Err(e) => {
+                match e.kind() {
+                    &ErrorKind::BadLength(cmd_byte, row, x) => {
+                        assert_eq!((0x00, 1, 1), (cmd_byte, row, x))
+                    }
+                    _ => panic!("unexpected error: {}", e),
+                }
+                assert_eq!("bad length in block copy command (0x00) at row 1, x 1", e.to_string());
+            }
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_max_dimension() {
+        let data = single_shape_slp(8193, &[(0x8000, 0x8000, &[])]);
+        match SlpFile::read_from(&mut io::Cursor::new(&data)) {
+            Ok(_) => panic!("expected the shape to be rejected"),
+            Err(e) => {
+                match e.kind() {
+                    &ErrorKind::InvalidSlp(ref reason) => assert!(reason.contains("8193x1")),
+                    _ => panic!("unexpected error: {}", e),
+                }
+            }
+        }
+
+        let options = DecodeOptions { max_dimension: 10000, ..DecodeOptions::default() };
+        assert!(SlpFile::read_from_with_options(&mut io::Cursor::new(&data), 1u8, &options).is_ok());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_is_fully_transparent() {
+        let data = single_shape_slp(2, &[(0x8000, 0x8000, &[]), (1, 0, &[0x04, 7, 0x0F])]);
+        let mut slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        assert!(!slp_file.shapes[0].is_fully_transparent());
+
+        slp_file.shapes[0].commands[1] = vec![DrawCommand::Skip { count: 2 }];
+        assert!(slp_file.shapes[0].is_fully_transparent());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_keeps_raw_shape() {
+        // Two one pixel copies where the encoder would use a single copy
+        let data = single_shape_slp(2, &[(0, 0, &[0x04, 7, 0x04, 8, 0x0F])]);
+        let mut slp_file = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        {
+            let raw = slp_file.shapes[0].unmodified_raw().unwrap();
+            assert_eq!(64, raw.offset);
+            assert_eq!(&data[64..], &raw.data[..]);
+        }
+        assert_eq!(data.len() as u64, slp_file.encoded_size().unwrap());
+
+        slp_file.shapes[0].pixels[1] = 9;
+        assert!(slp_file.shapes[0].unmodified_raw().is_none());
+        assert_eq!(data.len() as u64 - 1, slp_file.encoded_size().unwrap());
+    }
+
+    #[cfg(feature = "memmap")]
+    #[test]
+    fn test_slp_file_read_from_mmap() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+
+        let data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        let path = env::temp_dir().join("chariot_slp_test_read_from_mmap.slp");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let slp_file = SlpFile::read_from_mmap(&path, 1u8).unwrap();
+        assert_eq!(vec![7], slp_file.shapes[0].pixels);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_slp_file_read_from_file() {
+        use std::env;
+        use std::fs::{self, File};
+        use std::io::Write;
+
+        let data = single_shape_slp(1, &[(0, 0, &[0x16, 3, 0x0F])]);
+        let path = env::temp_dir().join("chariot_slp_test_read_from_file.slp");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        assert_eq!(vec![3], SlpFile::read_from_file(&path).unwrap().shapes[0].pixels);
+        assert_eq!(vec![16 + 3],
+                   SlpFile::read_from_file_baked(&path, 1u8).unwrap().shapes[0].pixels);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_slp_logical_shape_resize_canvas() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 2;
+        shape.header.height = 1;
+        shape.header.center_x = 1;
+        shape.pixels = vec![5, 6];
+        shape.commands = vec![vec![DrawCommand::Color { count: 1 },
+                                   DrawCommand::PlayerColor { relative: vec![6] }]];
+
+        shape.resize_canvas(3, 2, 1, 1);
+        assert_eq!((3, 2), (shape.header.width, shape.header.height));
+        assert_eq!((2, 1), (shape.header.center_x, shape.header.center_y));
+        assert_eq!(vec![0, 0, 0, 0, 5, 6], shape.pixels);
+        assert_eq!(vec![vec![DrawCommand::Skip { count: 3 }],
+                        vec![DrawCommand::Skip { count: 1 },
+                             DrawCommand::Color { count: 1 },
+                             DrawCommand::PlayerColor { relative: vec![6] }]],
+                   shape.commands);
+
+        // Content moved off the left edge is clipped
+        shape.resize_canvas(2, 1, -2, -1);
+        assert_eq!(vec![6, 0], shape.pixels);
+        assert_eq!(vec![vec![DrawCommand::PlayerColor { relative: vec![6] },
+                             DrawCommand::Skip { count: 1 }]],
+                   shape.commands);
+        assert_eq!((0, 0), (shape.header.center_x, shape.header.center_y));
+    }
+
+    #[test]
+    fn test_slp_file_read_from_with_trace() {
+        let data = single_shape_slp(4, &[(1, 0, &[0x04, 7, 0x27, 8, 0x0F]), (0x8000, 0x8000, &[])]);
+        let options = DecodeOptions::default();
+        let mut trace = Vec::new();
+        SlpFile::read_from_with_trace(&mut io::Cursor::new(data), 1u8, &options, &mut trace).unwrap();
+        assert_eq!("shape 0: 4x2\n\
+                    row 0, x 1: block copy (0x04), 1 pixels\n\
+                    row 0, x 2: fill (0x27), 2 pixels\n\
+                    row 0, x 4: end of line\n",
+                   String::from_utf8(trace).unwrap());
+    }
+
+    /// Reads from `cursor` until `fail_at`, then fails every read with an I/O error.
+    struct FailingReader {
+        cursor: io::Cursor<Vec<u8>>,
+        fail_at: u64,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let remaining = self.fail_at.saturating_sub(self.cursor.position()) as usize;
+            if remaining == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "simulated read failure"));
+            }
+            let length = ::std::cmp::min(remaining, buf.len());
+            self.cursor.read(&mut buf[..length])
+        }
+    }
+
+    impl Seek for FailingReader {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.cursor.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_io_error_context() {
+        // Row 1's commands span bytes 84 through 87
+        let data = single_shape_slp(2, &[(0, 0, &[0x08, 1, 2, 0x0F]), (0, 0, &[0x08, 3, 4, 0x0F])]);
+        let mut reader = FailingReader {
+            cursor: io::Cursor::new(data),
+            fail_at: 86,
+        };
+        match SlpFile::read_from(&mut reader) {
+            Ok(_) => panic!("expected a read error"),
+            Err(e) => {
+                match e.kind() {
+                    &ErrorKind::ShapeReadError(shape, row) => assert_eq!((0, 1), (shape, row)),
+                    _ => panic!("unexpected error: {}", e),
+                }
+                assert_eq!("failed to read shape 0 at row 1", e.to_string());
+            }
+        }
+    }
+
     #[test]
-    fn test_slp_header_read_from_bad_header() {
-        use std::io;
-        let data = "2.1N\x04\0\0\0test\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".as_bytes();
-        let result = SlpHeader::read_from(&mut io::Cursor::new(data));
-        match result {
-            Ok(_) => panic!("expected bad header error"),
-            Err(e) => {
-                match e.kind() {
-                    &ErrorKind::InvalidSlp(ref reason) => assert_eq!(*reason, "bad header".to_string()),
-                    _ => panic!("unexpected error: {}", e),
+    fn test_slp_file_read_from_records_row_offsets() {
+        let data = single_shape_slp(1,
+                                    &[(0, 0, &[0x04, 7, 0x0F]),
+                                      (0x8000, 0x8000, &[]),
+                                      (0, 0, &[0x04, 8, 0x0F])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        // Commands start after the header, shape header and two 3 row tables
+        assert_eq!(vec![88, 0, 91], slp_file.shapes[0].resolved_row_offsets);
+    }
+
+    #[test]
+    fn test_slp_file_index_frame_rows() {
+        let data = single_shape_slp(4, &[(1, 2, &[0x04, 7, 0x0F]), (0x8000, 0x8000, &[])]);
+        let mut cursor = io::Cursor::new(data);
+        let slp_file = SlpFile::read_from(&mut cursor).unwrap();
+        // Commands start after the header, shape header and two 2 row tables
+        assert_eq!(vec![(80, 1, 2), (0, 4, 0)],
+                   slp_file.index_frame_rows(&mut cursor, 0).unwrap());
+        assert!(slp_file.index_frame_rows(&mut cursor, 1).is_err());
+    }
+
+    #[test]
+    fn test_slp_file_index_frame_rows_zero_area() {
+        let mut data = build_slp(&[(1, &[(0, 0, &[0x04, 7, 0x0F])]), (0, &[])]);
+        // Point the empty shape's tables nowhere, and make it 0x5 instead
+        data[64..72].copy_from_slice(&[0xF0, 0xFF, 0xFF, 0xFF, 0xF0, 0xFF, 0xFF, 0xFF]);
+        data[84] = 5;
+        let mut cursor = io::Cursor::new(data);
+        let slp_file = SlpFile::read_from(&mut cursor).unwrap();
+        assert!(slp_file.index_frame_rows(&mut cursor, 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_row_is_transparent() {
+        let data = single_shape_slp(2,
+                                    &[(0, 1, &[0x04, 7, 0x0F]),
+                                      (0x8000, 0x8000, &[]),
+                                      (0, 0, &[0x09, 0x0F])]);
+        let shape = &SlpFile::read_from(&mut io::Cursor::new(data)).unwrap().shapes[0];
+        assert_eq!(vec![false, true, false], shape.transparent_rows);
+        assert!(!shape.row_is_transparent(0));
+        assert!(shape.row_is_transparent(1));
+        // Skipping the whole row is transparent too, even if the outline table doesn't say so
+        assert!(shape.row_is_transparent(2));
+    }
+
+    #[test]
+    fn test_slp_file_read_from_zero_area_shapes() {
+        let row: &[FixtureRow] = &[(0, 0, &[0x04, 7, 0x0F])];
+        let mut data = build_slp(&[(1, row), (0, &[]), (1, row)]);
+        // Point the empty shape's tables nowhere, and make it 0x5 instead
+        data[64..72].copy_from_slice(&[0xF0, 0xFF, 0xFF, 0xFF, 0xF0, 0xFF, 0xFF, 0xFF]);
+        data[84] = 5;
+
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(&data[..])).unwrap();
+        assert_eq!(vec![false, true, false],
+                   slp_file.shapes.iter().map(SlpLogicalShape::is_empty).collect::<Vec<_>>());
+        assert!(slp_file.shapes[1].pixels.is_empty());
+        assert_eq!(vec![7], slp_file.shapes[2].pixels);
+        SlpFile::validate(&mut io::Cursor::new(&data[..]), 1).unwrap();
+
+        // The 0x5 shape still has its 5 rows
+        let empty = &slp_file.shapes[1];
+        assert_eq!(5, empty.commands.len());
+        assert!(empty.row_is_transparent(4));
+        assert_eq!((0x8000, 0x8000), empty.row_outline(0));
+        let mut written = io::Cursor::new(Vec::new());
+        slp_file.write_to(&mut written).unwrap();
+        written.set_position(0);
+        let reread = SlpFile::read_from(&mut written).unwrap();
+        assert_eq!((0, 5), (reread.shapes[1].header.width, reread.shapes[1].header.height));
+        assert_eq!(vec![7], reread.shapes[2].pixels);
+
+        // Zero-area shapes are still held to the maximum dimension
+        data[84..88].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        match SlpFile::read_from(&mut io::Cursor::new(&data[..])) {
+            Err(Error(ErrorKind::InvalidSlp(reason), _)) => {
+                assert!(reason.contains("exceeds the maximum dimension"), "{}", reason)
+            }
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_group_by_directions() {
+        let mut slp_file = SlpFile::new();
+        for width in 0..10 {
+            let mut shape = SlpLogicalShape::new();
+            shape.header.width = width;
+            slp_file.shapes.push(shape);
+        }
+        let directions = slp_file.group_by_directions(5).unwrap();
+        assert_eq!(5, directions.len());
+        assert_eq!(vec![4, 5],
+                   directions[2].iter().map(|shape| shape.header.width).collect::<Vec<_>>());
+
+        assert!(slp_file.group_by_directions(3).is_err());
+        assert!(slp_file.group_by_directions(0).is_err());
+        assert_eq!(None, slp_file.num_directions());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_row_outline() {
+        let data = single_shape_slp(3,
+                                    &[(1, 1, &[0x04, 7, 0x0F]),
+                                      (0x8000, 0x8000, &[]),
+                                      (0, 0, &[0x0D, 0x0F])]);
+        let mut shape = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap().shapes.remove(0);
+        assert_eq!(vec![(1, 1), (0x8000, 0x8000), (0, 0)], shape.outline);
+        assert_eq!((0x8000, 0x8000), shape.row_outline(1));
+
+        // Without a decoded outline, it's worked out from the commands
+        shape.outline.clear();
+        assert_eq!((1, 1), shape.row_outline(0));
+        assert_eq!((0x8000, 0x8000), shape.row_outline(1));
+        assert_eq!((0x8000, 0x8000), shape.row_outline(2));
+    }
+
+    #[test]
+    fn test_slp_logical_shape_swap_index_range() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 5;
+        shape.header.height = 1;
+        shape.pixels = vec![10, 11, 12, 11, 11];
+        shape.commands = vec![vec![DrawCommand::Color { count: 3 },
+                                   DrawCommand::PlayerColor { relative: vec![11] },
+                                   DrawCommand::Fill { count: 1 }]];
+
+        shape.swap_index_range(10..12, 100);
+        assert_eq!(vec![100, 101, 12, 11, 101], shape.pixels);
+        assert_eq!(vec![vec![DrawCommand::Color { count: 3 },
+                             DrawCommand::PlayerColor { relative: vec![11] },
+                             DrawCommand::Fill { count: 1 }]],
+                   shape.commands);
+    }
+
+    #[test]
+    fn test_slp_file_memory_size() {
+        use std::mem::size_of;
+        let mut slp_file = SlpFile::new();
+        let empty_size = slp_file.memory_size();
+
+        let mut shape = SlpLogicalShape::new();
+        shape.pixels = Vec::with_capacity(100);
+        shape.commands = Vec::with_capacity(10);
+        shape.commands.push(vec![DrawCommand::PlayerColor { relative: Vec::with_capacity(20) }]);
+        assert_eq!(size_of::<SlpLogicalShape>() + 100 + 10 * size_of::<Vec<DrawCommand>>() +
+                   shape.commands[0].capacity() * size_of::<DrawCommand>() + 20,
+                   shape.memory_size());
+
+        let shape_size = shape.memory_size();
+        slp_file.shapes = vec![shape];
+        assert_eq!(empty_size + shape_size, slp_file.memory_size());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_no_player() {
+        // A copy and colorize of relative indices 1 and 2, then a transform of relative index 3
+        let data = single_shape_slp(4, &[(0, 0, &[0x26, 1, 2, 0x2A, 3, 0x0F])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        let shape = &slp_file.shapes[0];
+        assert_eq!(vec![1, 2, 3, 3], shape.pixels);
+        assert_eq!(vec![vec![DrawCommand::PlayerColor { relative: vec![1, 2, 3, 3] }]],
+                   shape.commands);
+    }
+
+    #[test]
+    fn test_slp_logical_shape_shadow_mask() {
+        // A shadow run followed by a color, so the color only lines up if the shadow advanced x
+        let data = single_shape_slp(4, &[(1, 0, &[0x2B, 0x04, 7, 0x0F])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        let shape = &slp_file.shapes[0];
+        assert_eq!(vec![false, true, true, false], shape.shadow_mask());
+        assert_eq!(vec![0, 0, 0, 7], shape.pixels);
+    }
+
+    #[test]
+    fn test_slp_file_read_from_shadow_then_colors() {
+        // 3 shadow pixels, then 2 colors that fill the rest of the row
+        let data = single_shape_slp(6, &[(0, 1, &[0x3B, 0x08, 7, 8, 0x0F])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        let shape = &slp_file.shapes[0];
+        assert_eq!(vec![vec![DrawCommand::Shadow { count: 3 },
+                             DrawCommand::Color { count: 2 },
+                             DrawCommand::Skip { count: 1 }]],
+                   shape.commands);
+        assert_eq!(vec![0, 0, 0, 7, 8, 0], shape.pixels);
+    }
+
+    #[test]
+    fn test_slp_file_read_from_with_len() {
+        let data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        let len = data.len() as u64;
+        let slp_file = SlpFile::read_from_with_len(&mut io::Cursor::new(&data), len, 1u8).unwrap();
+        assert_eq!(vec![7], slp_file.shapes[0].pixels);
+
+        // The outline table starts right after the 64 bytes of headers
+        match SlpFile::read_from_with_len(&mut io::Cursor::new(&data), 66, 1u8) {
+            Err(Error(ErrorKind::InvalidSlp(reason), _)) => assert!(reason.contains("outline")),
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_player_color_frames() {
+        let mut slp_file = SlpFile::new();
+        for command in &[DrawCommand::Color { count: 1 },
+                         DrawCommand::PlayerColor { relative: vec![1] },
+                         DrawCommand::Shadow { count: 1 },
+                         DrawCommand::PlayerColor { relative: vec![1] }] {
+            let mut shape = SlpLogicalShape::new();
+            shape.commands = vec![vec![DrawCommand::Skip { count: 1 }, command.clone()]];
+            slp_file.shapes.push(shape);
+        }
+
+        assert!(!slp_file.shapes[0].has_player_color());
+        assert_eq!(vec![1, 3], slp_file.player_color_frames());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_row_offset_past_end() {
+        let mut data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        // Point the row's commands well past the end of the file
+        let row_offset = data.len() as u32 + 100;
+        data[68..72].copy_from_slice(&[row_offset as u8, (row_offset >> 8) as u8, 0, 0]);
+        match SlpFile::read_from(&mut io::Cursor::new(data)) {
+            Err(Error(ErrorKind::DecodeError(0, 0, reason), _)) => {
+                assert!(reason.starts_with(&format!("offset {} ", row_offset)))
+            }
+            other => panic!("expected DecodeError, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_column_major() {
+        use options::PixelLayout;
+        let data = single_shape_slp(3, &[(0, 0, &[0x0C, 1, 2, 3, 0x0F]), (0, 1, &[0x08, 4, 5, 0x0F])]);
+        let options = DecodeOptions { layout: PixelLayout::ColumnMajor, ..DecodeOptions::default() };
+        let slp_file = SlpFile::read_from_with_options(&mut io::Cursor::new(data), 1u8, &options)
+            .unwrap();
+        let shape = &slp_file.shapes[0];
+        assert_eq!(vec![1, 4, 2, 5, 3, 0], shape.pixels);
+        // The runs are kept by row whatever the layout
+        assert_eq!(vec![DrawCommand::Color { count: 2 }, DrawCommand::Skip { count: 1 }],
+                   shape.commands[1]);
+    }
+
+    #[test]
+    fn test_slp_file_dedup_frames() {
+        let mut slp_file = SlpFile::new();
+        for &(index, center_x) in &[(1, 0), (1, 0), (2, 0), (2, 5), (1, 0)] {
+            let mut shape = SlpLogicalShape::new();
+            shape.header.width = 1;
+            shape.header.height = 1;
+            shape.header.center_x = center_x;
+            shape.pixels = vec![index];
+            shape.commands = vec![vec![DrawCommand::Color { count: 1 }]];
+            slp_file.shapes.push(shape);
+        }
+        slp_file.header.shape_count = 5;
+
+        assert_eq!(vec![0, 0, 1, 2, 3], slp_file.dedup_frames());
+        assert_eq!(4, slp_file.shapes.len());
+        assert_eq!(4, slp_file.header.shape_count);
+        assert_eq!(5, slp_file.shapes[2].header.center_x);
+
+        // Shapes drawn with other properties aren't duplicates, even with the same pixels
+        let mut shape = slp_file.shapes[0].clone();
+        shape.header.properties = 0x08;
+        slp_file.shapes.insert(1, shape);
+        assert_eq!(vec![0, 1, 2, 3, 4], slp_file.dedup_frames());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_extended_commands() {
+        // A player color outline pixel, a 2 pixel black outline span, a render hint, a black
+        // outline pixel and a 2 pixel player color outline span, between colors
+        let data = single_shape_slp(8,
+                                    &[(0,
+                                       0,
+                                       &[0x04, 7, 0x4E, 0x7E, 2, 0x0E, 0x6E, 0x5E, 2, 0x04, 8,
+                                         0x0F])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        let shape = &slp_file.shapes[0];
+        assert_eq!(vec![7, 0, 0, 0, 0, 0, 0, 8], shape.pixels);
+        assert_eq!(vec![vec![DrawCommand::Color { count: 1 },
+                             DrawCommand::Outline { count: 1 },
+                             DrawCommand::BlackOutline { count: 3 },
+                             DrawCommand::Outline { count: 2 },
+                             DrawCommand::Color { count: 1 }]],
+                   shape.commands);
+        assert_eq!(vec![RawCommand { x: 4, y: 0, length: 0, bytes: vec![0x0E] }],
+                   shape.raw_commands);
+    }
+
+    #[test]
+    fn test_slp_file_read_from_unknown_command() {
+        let data = single_shape_slp(1, &[(0, 0, &[0xFE, 0x0F])]);
+        match SlpFile::read_from(&mut io::Cursor::new(data)) {
+            Err(Error(ErrorKind::DecodeError(0, 0, reason), _)) => {
+                assert!(reason.contains("unknown command byte 0xfe at row 0"), "{}", reason)
+            }
+            other => panic!("expected DecodeError, got {:?}", other.map(|_| ())),
+        }
+
+        // With another end of line marker, 0x0F is no longer a known command
+        let data = single_shape_slp(1, &[(0, 0, &[0x0F, 0xFF])]);
+        let mut options = DecodeOptions::default();
+        options.end_of_line = 0xFF;
+        match SlpFile::read_from_with_options(&mut io::Cursor::new(data), 1u8, &options) {
+            Err(Error(ErrorKind::DecodeError(0, 0, reason), _)) => {
+                assert!(reason.contains("unknown command byte 0x0f at row 0"), "{}", reason)
+            }
+            other => panic!("expected DecodeError, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_runaway_row() {
+        // Zero length large skips go nowhere, so the row never reaches its end of line
+        let mut commands = Vec::new();
+        for _ in 0..20 {
+            commands.extend_from_slice(&[0x03, 0x00]);
+        }
+        commands.push(0x0F);
+        let data = single_shape_slp(1, &[(1, 0, &commands)]);
+        match SlpFile::read_from(&mut io::Cursor::new(data)) {
+            Err(Error(ErrorKind::DecodeError(0, 0, reason), _)) => {
+                assert!(reason.contains("row 0 has more than 18 commands"), "{}", reason)
+            }
+            other => panic!("expected DecodeError, got {:?}", other.map(|_| ())),
+        }
+
+        // A fill that runs past the end of the row
+        let data = single_shape_slp(2, &[(0, 0, &[0x37, 9, 0x0F])]);
+        match SlpFile::read_from(&mut io::Cursor::new(data)) {
+            Err(Error(ErrorKind::DecodeError(0, 0, reason), _)) => {
+                assert!(reason.contains("run of 3 pixels at row 0, x 0"), "{}", reason)
+            }
+            other => panic!("expected DecodeError, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_overlong_runs() {
+        // Every command that writes pixels, each one pixel too long for a 2 pixel wide shape
+        let rows: &[&[u8]] = &[&[0x0C, 1, 2, 3, 0x0F],
+                               &[0x02, 3, 1, 2, 3, 0x0F],
+                               &[0x36, 1, 2, 3, 0x0F],
+                               &[0x37, 1, 0x0F],
+                               &[0x3A, 1, 0x0F],
+                               &[0x3B, 0x0F],
+                               &[0x5E, 3, 0x0F],
+                               &[0x09, 0x4E, 0x0F]];
+        for commands in rows {
+            let data = single_shape_slp(2, &[(0, 0, commands)]);
+            match SlpFile::read_from(&mut io::Cursor::new(data)) {
+                Err(Error(ErrorKind::DecodeError(0, 0, reason), _)) => {
+                    assert!(reason.contains("at row 0, x"), "{:?}: {}", commands, reason)
+                }
+                other => panic!("{:?}: expected DecodeError, got {:?}", commands, other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_slp_file_frame_anchors() {
+        let mut slp_file = SlpFile::new();
+        for &center in &[(3, 4), (-1, 0)] {
+            let mut shape = SlpLogicalShape::new();
+            shape.header.center_x = center.0;
+            shape.header.center_y = center.1;
+            slp_file.shapes.push(shape);
+        }
+        assert_eq!((3, 4), slp_file.shapes[0].anchor());
+        assert_eq!(vec![(3, 4), (-1, 0)], slp_file.frame_anchors());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_bytes_many_shapes() {
+        // Every frame draws its own index, so shapes decoded out of order would be noticed
+        let commands: Vec<Vec<u8>> = (0..256).map(|i| vec![0x08, i as u8, 7, 0x0F]).collect();
+        let rows: Vec<Vec<FixtureRow>> = commands.iter()
+            .map(|commands| vec![(0, 1, &commands[..]), (1, 0, &[0x08, 1, 2, 0x0F][..])])
+            .collect();
+        let shapes: Vec<(u32, &[FixtureRow])> = rows.iter().map(|rows| (3, &rows[..])).collect();
+        let data = build_slp(&shapes);
+
+        let serial = SlpFile::read_from_with_len(&mut io::Cursor::new(&data[..]),
+                                                 data.len() as u64,
+                                                 1)
+            .unwrap();
+        let slp_file = SlpFile::read_from_bytes(&data, 1).unwrap();
+        assert_eq!(256, slp_file.shapes.len());
+        for (shape, expected) in slp_file.shapes.iter().zip(&serial.shapes) {
+            assert_eq!(expected.header.width, shape.header.width);
+            assert_eq!(expected.pixels, shape.pixels);
+            assert_eq!(expected.commands, shape.commands);
+        }
+        assert_eq!(255, slp_file.shapes[255].pixels[0]);
+    }
+
+    #[test]
+    fn test_slp_file_validate() {
+        let data = build_slp(&[(2, &[(0, 0, &[0x08, 7, 8, 0x0F])]),
+                               (2, &[(0, 0, &[0x2B, 0x0F]), (0x8000, 0x8000, &[])])]);
+        SlpFile::validate(&mut io::Cursor::new(&data[..]), 1).unwrap();
+
+        // The second shape's last row is one pixel short
+        let data = build_slp(&[(2, &[(0, 0, &[0x08, 7, 8, 0x0F])]),
+                               (2, &[(0, 0, &[0x08, 7, 8, 0x0F]), (0, 0, &[0x04, 7, 0x0F])])]);
+        match SlpFile::validate(&mut io::Cursor::new(&data[..]), 1) {
+            Err(Error(ErrorKind::DecodeError(1, 1, ref reason), _)) => {
+                assert!(reason.starts_with("Line 1 not the expected size"), "{}", reason)
+            }
+            other => panic!("expected DecodeError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_frame_from_slice_arbitrary_bytes() {
+        // xorshift, so the inputs are the same on every run
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let valid = single_shape_slp(4,
+                                     &[(1, 0, &[0x08, 7, 8, 0x1A, 3, 0x0F]),
+                                       (0x8000, 0x8000, &[]),
+                                       (0, 0, &[0x05, 0x27, 9, 0x4E, 0x0F])]);
+        assert!(decode_frame_from_slice(&valid).is_ok());
+        for _ in 0..4000 {
+            // Corrupt a few bytes of a valid file, so decoding gets past the headers
+            let mut data = valid.clone();
+            for _ in 0..(next() % 4 + 1) {
+                let i = next() as usize % data.len();
+                data[i] = next() as u8;
+            }
+            let _ = decode_frame_from_slice(&data);
+
+            let len = next() as usize % 128;
+            let mut data: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+            if len >= 4 && next() % 2 == 0 {
+                data[..4].copy_from_slice(b"2.0N");
+            }
+            let _ = decode_frame_from_slice(&data);
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_buffered() {
+        let data = build_slp(&[(2, &[(0, 0, &[0x08, 7, 8, 0x0F])]),
+                               (1, &[(0, 0, &[0x06, 1, 3, 0x0F])])]);
+        // A slice can be read but not seeked
+        let slp_file = SlpFile::read_from_buffered(&mut &data[..], 1).unwrap();
+        assert_eq!(vec![7, 8], slp_file.shapes[0].pixels);
+        assert_eq!(vec![16 + 3], slp_file.shapes[1].pixels);
+    }
+
+    #[test]
+    fn test_slp_file_read_many() {
+        let first = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        let second = single_shape_slp(2, &[(0, 0, &[0x08, 8, 9, 0x0F])]);
+        let mut data = first.clone();
+        data.extend_from_slice(&second);
+        data.extend_from_slice(&first);
+        let ranges = [(0, first.len()),
+                      (first.len(), second.len()),
+                      // Truncated partway through its commands
+                      (first.len() + second.len(), first.len() - 2),
+                      (data.len() - 1, 2)];
+
+        let results = SlpFile::read_many(&data, &ranges, 1);
+        assert_eq!(4, results.len());
+        assert_eq!(vec![7], results[0].as_ref().unwrap().shapes[0].pixels);
+        assert_eq!(vec![8, 9], results[1].as_ref().unwrap().shapes[0].pixels);
+        assert!(results[2].is_err());
+        match results[3] {
+            Err(Error(ErrorKind::InvalidSlp(ref reason), _)) => {
+                assert!(reason.contains("outside the"), "{}", reason)
+            }
+            ref other => panic!("expected InvalidSlp, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_at() {
+        let slp = build_slp(&[(2, &[(0, 0, &[0x08, 7, 8, 0x0F])]),
+                              (3, &[(1, 0, &[0x0A, 2, 3, 0x0F]), (0x8000, 0x8000, &[])])]);
+        let mut archive = vec![0xAAu8; 100];
+        archive.extend_from_slice(&slp);
+        archive.extend_from_slice(&[0xBB; 50]);
+
+        let expected = SlpFile::read_from_baked(&mut io::Cursor::new(&slp[..]), 1).unwrap();
+        let mut cursor = io::Cursor::new(&archive[..]);
+        let slp_file = SlpFile::read_from_at(&mut cursor, 100, 1).unwrap();
+        assert_eq!(expected.shapes.len(), slp_file.shapes.len());
+        for (shape, expected) in slp_file.shapes.iter().zip(&expected.shapes) {
+            assert_eq!(expected.pixels, shape.pixels);
+            assert_eq!(expected.commands, shape.commands);
+        }
+
+        assert!(SlpFile::read_from_at(&mut cursor, 99, 1).is_err());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_blit_rect() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 10;
+        shape.header.height = 20;
+        shape.header.center_x = 3;
+        shape.header.center_y = -4;
+        assert_eq!((97, 54, 10, 20), shape.blit_rect((100, 50)));
+    }
+
+    #[test]
+    fn test_slp_logical_shape_verify() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 2;
+        shape.header.height = 1;
+        assert!(shape.verify().is_err());
+
+        shape.pixels = vec![0, 0];
+        shape.commands = vec![vec![DrawCommand::Skip { count: 1 }]];
+        assert!(shape.verify().is_err());
+
+        shape.commands = vec![vec![DrawCommand::Skip { count: 1 }, DrawCommand::Skip { count: 1 }]];
+        shape.verify().unwrap();
+
+        shape.raw_commands.push(RawCommand { x: 1, y: 0, length: 2, bytes: vec![0x5E, 2] });
+        assert!(shape.verify().is_err());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_from_parts() {
+        use super::SlpShapeHeader;
+        let mut header = SlpShapeHeader::new();
+        header.width = 2;
+        header.height = 1;
+        let row = vec![DrawCommand::Color { count: 1 }, DrawCommand::PlayerColor { relative: vec![4] }];
+        let shape = SlpLogicalShape::from_parts(header.clone(), vec![3, 16 + 4], vec![row.clone()])
+            .unwrap();
+        assert_eq!(vec![3, 16 + 4], shape.pixels);
+
+        header.height = 2;
+        assert!(SlpLogicalShape::from_parts(header, vec![3, 4], vec![row]).is_err());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_copy_and_colorize() {
+        // Copy and colorize relative indices 0, 5, 15 and 17
+        let data = single_shape_slp(4, &[(0, 0, &[0x46, 0, 5, 15, 17, 0x0F])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        let shape = &slp_file.shapes[0];
+        assert_eq!(vec![0, 5, 15, 17], shape.pixels);
+        assert_eq!(vec![vec![DrawCommand::PlayerColor { relative: vec![0, 5, 15, 17] }]],
+                   shape.commands);
+
+        let slp_file = SlpFile::read_from_baked(&mut io::Cursor::new(&data), 1u8).unwrap();
+        assert_eq!(vec![16, 21, 31, 33], slp_file.shapes[0].pixels);
+    }
+
+    #[test]
+    fn test_slp_logical_shape_apply_player() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 3;
+        shape.header.height = 1;
+        shape.pixels = vec![5, 5, 0];
+        shape.commands = vec![vec![DrawCommand::Color { count: 1 },
+                                   DrawCommand::PlayerColor { relative: vec![5] },
+                                   DrawCommand::Skip { count: 1 }]];
+        assert_eq!(vec![5, 32 + 5, 0], shape.apply_player(2));
+
+        // Already baked for player 1
+        shape.pixels[1] = 16 + 5;
+        assert_eq!(vec![5, 48 + 5, 0], shape.apply_player(3));
+    }
+
+    #[test]
+    fn test_slp_logical_shape_recolor() {
+        let data = single_shape_slp(3, &[(0, 0, &[0x04, 3, 0x26, 4, 15, 0x0F])]);
+        let mut shape = SlpFile::read_from_baked(&mut io::Cursor::new(&data), 2u8)
+            .unwrap()
+            .shapes
+            .remove(0);
+        assert_eq!(vec![3, 32 + 4, 32 + 15], shape.pixels);
+
+        shape.recolor(5);
+        assert_eq!(vec![3, 80 + 4, 80 + 15], shape.pixels);
+        shape.recolor(NO_PLAYER);
+        assert_eq!(vec![3, 4, 15], shape.pixels);
+        shape.recolor(1);
+        assert_eq!(vec![3, 16 + 4, 16 + 15], shape.pixels);
+
+        // The base player colors were decoded with doesn't need to be known
+        let options = DecodeOptions { player_color_base: 100, ..DecodeOptions::default() };
+        let mut shape = SlpFile::read_from_with_options(&mut io::Cursor::new(data), 2u8, &options)
+            .unwrap()
+            .shapes
+            .remove(0);
+        assert_eq!(vec![3, 132 + 4, 132 + 15], shape.pixels);
+        shape.recolor(1);
+        assert_eq!(vec![3, 16 + 4, 16 + 15], shape.pixels);
+    }
+
+    #[test]
+    fn test_slp_file_read_from_baked() {
+        let data = single_shape_slp(2, &[(0, 0, &[0x26, 3, 4, 0x0F])]);
+        let slp_file = SlpFile::read_from_baked(&mut io::Cursor::new(data), 2u8).unwrap();
+        assert_eq!(vec![32 + 3, 32 + 4], slp_file.shapes[0].pixels);
+    }
+
+    #[test]
+    fn test_slp_logical_shape_occupancy() {
+        let mut shape = SlpLogicalShape::new();
+        assert_eq!(0.0, shape.occupancy());
+
+        shape.header.width = 2;
+        shape.header.height = 2;
+        shape.pixels = vec![0; 4];
+        shape.commands = vec![vec![DrawCommand::Color { count: 1 }, DrawCommand::Skip { count: 1 }],
+                              vec![DrawCommand::Shadow { count: 1 },
+                                   DrawCommand::Skip { count: 1 }]];
+        assert_eq!(2, shape.drawn_pixel_count());
+        assert_eq!(0.5, shape.occupancy());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_lenient_row_end() {
+        // Right padding of 1, but four pixels are drawn
+        let data = single_shape_slp(4, &[(0, 1, &[0x10, 1, 2, 3, 4, 0x0F])]);
+        assert!(SlpFile::read_from(&mut io::Cursor::new(&data)).is_err());
+
+        let options = DecodeOptions { lenient_row_end: true, ..DecodeOptions::default() };
+        let slp_file = SlpFile::read_from_with_options(&mut io::Cursor::new(&data), 1u8, &options)
+            .unwrap();
+        assert_eq!(vec![1, 2, 3, 4], slp_file.shapes[0].pixels);
+    }
+
+    #[test]
+    fn test_slp_file_animation_bounds() {
+        let mut slp_file = SlpFile::new();
+        assert_eq!((0, 0, 0, 0), slp_file.animation_bounds());
+
+        for &(drawn, center) in &[(1, (1, 1)), (2, (0, 0)), (4, (0, 0))] {
+            let mut shape = SlpLogicalShape::new();
+            shape.header.width = 2;
+            shape.header.height = 2;
+            shape.header.center_x = center.0;
+            shape.header.center_y = center.1;
+            shape.pixels = vec![0; 4];
+            let mut kinds = [PixelKind::Skip; 4];
+            if drawn < 4 {
+                kinds[drawn] = PixelKind::Color;
+            }
+            set_pixel_kinds(&mut shape, &kinds);
+            slp_file.shapes.push(shape);
+        }
+        assert_eq!(Some((0, -1, 1, 0)), slp_file.shapes[0].bounds_relative_to_center());
+        assert_eq!(Some((0, 1, 1, 2)), slp_file.shapes[1].bounds_relative_to_center());
+        assert_eq!(None, slp_file.shapes[2].bounds_relative_to_center());
+        assert_eq!((0, -1, 1, 2), slp_file.animation_bounds());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_cancelable() {
+        use std::sync::atomic::AtomicBool;
+        let data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        let abort = AtomicBool::new(false);
+        let slp_file = SlpFile::read_from_cancelable(&mut io::Cursor::new(&data), 1u8, &abort)
+            .unwrap();
+        assert_eq!(vec![7], slp_file.shapes[0].pixels);
+
+        abort.store(true, ::std::sync::atomic::Ordering::Relaxed);
+        match SlpFile::read_from_cancelable(&mut io::Cursor::new(&data), 1u8, &abort) {
+            Err(Error(ErrorKind::Aborted, _)) => {}
+            other => panic!("expected Aborted, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_default_palette_id_for() {
+        use super::{DEFAULT_PALETTE_ID, default_palette_id_for};
+        assert_eq!(Some(DEFAULT_PALETTE_ID), default_palette_id_for(0));
+        assert_eq!(None, default_palette_id_for(3));
+    }
+
+    #[test]
+    fn test_slp_file_read_from_row_offset_into_tables() {
+        let mut data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        // Point the row's commands at its own outline entry
+        data[68..72].copy_from_slice(&[64, 0, 0, 0]);
+        match SlpFile::read_from(&mut io::Cursor::new(data)) {
+            Err(Error(ErrorKind::DecodeError(0, 0, reason), _)) => {
+                assert!(reason.contains("before the command section at 72"), "{}", reason)
+            }
+            other => panic!("expected DecodeError, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_logical_shape_clone_and_debug() {
+        let data = single_shape_slp(2, &[(0, 0, &[0x08, 7, 8, 0x0F])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        let mut copy = slp_file.clone();
+        copy.shapes[0].pixels[0] = 9;
+        assert_eq!(vec![7, 8], slp_file.shapes[0].pixels);
+
+        let debug = format!("{:?}", slp_file.shapes[0]);
+        assert!(debug.starts_with("SlpLogicalShape { header: SlpShapeHeader {"), "{}", debug);
+        assert!(debug.contains("pixels: 2, commands: 1"), "{}", debug);
+        assert!(format!("{:?}", slp_file).starts_with("SlpFile { header: SlpHeader {"));
+    }
+
+    #[test]
+    fn test_slp_shape_header_properties() {
+        let mut header = SlpShapeHeader::new();
+        header.properties = 0x0418;
+        assert_eq!(0x18, header.palette_id());
+        assert_eq!(0x04, header.render_flags());
+        assert_eq!(0x0418, header.properties_raw());
+    }
+
+    #[test]
+    fn test_table_entry_offset() {
+        use super::table_entry_offset;
+        assert_eq!(0xFFFF_FFFC, table_entry_offset(0xFFFF_FFF8, 1).unwrap());
+        match table_entry_offset(0xFFFF_FFFE, 1) {
+            Err(Error(ErrorKind::InvalidSlp(reason), _)) => {
+                assert!(reason.starts_with("row 1's entry"), "{}", reason)
+            }
+            other => panic!("expected InvalidSlp, got {:?}", other),
+        }
+        assert!(table_entry_offset(0, 0x4000_0000).is_err());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_overflowing_outline_offset() {
+        let mut data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F]), (0, 0, &[0x04, 7, 0x0F])]);
+        data[36..40].copy_from_slice(&[0xFE, 0xFF, 0xFF, 0xFF]);
+        match SlpFile::read_from(&mut io::Cursor::new(data)) {
+            Err(Error(ErrorKind::InvalidSlp(_), _)) => {}
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_half_transparent_row() {
+        for &(left, right) in &[(0x8000, 0), (1, 0x8000)] {
+            let data = single_shape_slp(2, &[(0, 0, &[0x08, 7, 8, 0x0F]), (left, right, &[])]);
+            match SlpFile::read_from(&mut io::Cursor::new(data)) {
+                Err(Error(ErrorKind::DecodeError(0, 1, reason), _)) => {
+                    assert!(reason.contains("malformed outline pair"), "{}", reason)
                 }
+                other => panic!("expected DecodeError, got {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_slp_file_read_from_end_of_line_high_bits() {
+        let data = single_shape_slp(2, &[(0, 0, &[0x08, 7, 8, 0x1F]), (1, 0, &[0x04, 9, 0xFF])]);
+        let slp_file = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        assert_eq!(vec![7, 8, 0, 9], slp_file.shapes[0].pixels);
+    }
+
+    #[test]
+    fn test_slp_file_read_from_format_options() {
+        // A transparent row marked with 0x7FFF, and a row ending with 0x1F
+        let data = single_shape_slp(2, &[(0x7FFF, 0x7FFF, &[]), (0, 0, &[0x26, 1, 2, 0x1F])]);
+        let options = DecodeOptions {
+            end_of_line: 0x1F,
+            transparent_row: 0x7FFF,
+            player_color_base: 100,
+            ..DecodeOptions::default()
+        };
+        let slp_file = SlpFile::read_from_with_options(&mut io::Cursor::new(&data), 1u8, &options)
+            .unwrap();
+        assert_eq!(vec![0, 0, 100 + 16 + 1, 100 + 16 + 2], slp_file.shapes[0].pixels);
+
+        assert!(SlpFile::read_from(&mut io::Cursor::new(&data)).is_err());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_index_histogram() {
+        let mut shape = SlpLogicalShape::new();
+        shape.pixels = vec![4, 4, 9, 4, 0, 4, 4];
+        shape.commands = vec![vec![DrawCommand::Color { count: 3 },
+                                   DrawCommand::PlayerColor { relative: vec![4] },
+                                   DrawCommand::Skip { count: 1 },
+                                   DrawCommand::Fill { count: 2 }]];
+        let histogram = shape.index_histogram();
+        assert_eq!(4, histogram[4]);
+        assert_eq!(1, histogram[9]);
+        assert_eq!(5, histogram.iter().sum::<u32>());
+    }
+
+    #[test]
+    fn test_slp_file_read_from_reject_trailing_data() {
+        let mut data = single_shape_slp(1, &[(0, 0, &[0x04, 7, 0x0F])]);
+        let options = DecodeOptions { reject_trailing_data: true, ..DecodeOptions::default() };
+        SlpFile::read_from_with_options(&mut io::Cursor::new(&data), 1u8, &options).unwrap();
+
+        data.extend_from_slice(&[0, 0]);
+        SlpFile::read_from(&mut io::Cursor::new(&data)).unwrap();
+        match SlpFile::read_from_with_options(&mut io::Cursor::new(&data), 1u8, &options) {
+            Err(Error(ErrorKind::InvalidSlp(reason), _)) => {
+                assert!(reason.starts_with("2 bytes of unexpected data"), "{}", reason)
             }
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
         }
     }
+
+    #[test]
+    fn test_slp_logical_shape_moves_clear_row_tables() {
+        let data = single_shape_slp(2, &[(0, 0, &[0x08, 7, 8, 0x0F]), (0x8000, 0x8000, &[])]);
+        let mut shape = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap().shapes.remove(0);
+        assert!(shape.row_is_transparent(1));
+
+        shape.resize_canvas(2, 2, 0, 1);
+        assert!(shape.row_is_transparent(0));
+        assert!(!shape.row_is_transparent(1));
+        assert_eq!((0x8000, 0x8000), shape.row_outline(0));
+        assert_eq!((0, 0), shape.row_outline(1));
+
+        shape.rotate_90(true);
+        assert!(!shape.row_is_transparent(0));
+        assert!(!shape.row_is_transparent(1));
+        assert_eq!((0, 1), shape.row_outline(0));
+        assert_eq!((0, 1), shape.row_outline(1));
+        assert!(shape.resolved_row_offsets.is_empty());
+    }
+
+    #[test]
+    fn test_slp_logical_shape_rotate_90() {
+        // 3x2:
+        // 1 2 3
+        // 4 5 6
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 3;
+        shape.header.height = 2;
+        shape.header.center_x = 1;
+        shape.header.center_y = 0;
+        shape.pixels = vec![1, 2, 3, 4, 5, 6];
+        set_pixel_kinds(&mut shape,
+                        &[PixelKind::Remap,
+                          PixelKind::Color,
+                          PixelKind::Color,
+                          PixelKind::Color,
+                          PixelKind::Color,
+                          PixelKind::Color]);
+
+        shape.rotate_90(true);
+        assert_eq!((2, 3), (shape.header.width, shape.header.height));
+        assert_eq!(vec![4, 1, 5, 2, 6, 3], shape.pixels);
+        assert_eq!(vec![DrawCommand::Color { count: 1 },
+                        DrawCommand::PlayerColor { relative: vec![1] }],
+                   shape.commands[0]);
+        assert_eq!((2, 1), shape.anchor());
+
+        shape.rotate_90(false);
+        assert_eq!((3, 2), (shape.header.width, shape.header.height));
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], shape.pixels);
+        assert_eq!(vec![DrawCommand::PlayerColor { relative: vec![1] },
+                        DrawCommand::Color { count: 2 }],
+                   shape.commands[0]);
+        assert_eq!((1, 0), shape.anchor());
+
+        shape.rotate_90(false);
+        assert_eq!(vec![3, 6, 2, 5, 1, 4], shape.pixels);
+        assert_eq!((0, 2), shape.anchor());
+    }
 }
@@ -0,0 +1,175 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+use encode::{SHAPE_HEADER_SIZE, SLP_HEADER_SIZE, encode_shape_at, file_offset, u32_bytes};
+use error::*;
+use options::WriteOptions;
+use slp::{SlpHeader, SlpLogicalShape, SlpShapeHeader};
+use std::io::{Seek, SeekFrom, Write};
+
+/// Writes an SLP one frame at a time, so frames can be dropped as soon as they're written.
+///
+/// Shape headers have to come right after the file header, so room for `max_frames` of them is
+/// reserved up front. Each frame's header is filled in as the frame is written, and
+/// [finish](#method.finish) fills in the final frame count.
+pub struct SlpStreamWriter<W: Write + Seek> {
+    stream: W,
+    start: u64,
+    max_frames: u32,
+    frame_count: u32,
+    end: u32,
+    options: WriteOptions,
+}
+
+impl<W: Write + Seek> SlpStreamWriter<W> {
+    /// Writes the file header to `stream` and reserves room for `max_frames` frames.
+    ///
+    /// `comment` is stored in the header as by
+    /// [SlpHeader::with_version_and_comment](struct.SlpHeader.html#method.with_version_and_comment),
+    /// so it can be at most 24 Latin-1 characters. Fails with `InvalidSlp` if the reserved
+    /// headers wouldn't fit in the 4 GiB an SLP can address.
+    pub fn begin(mut stream: W, comment: &str, max_frames: u32) -> Result<SlpStreamWriter<W>> {
+        let header = try!(SlpHeader::with_version_and_comment("2.0N", comment));
+        let end = try!(file_offset(SLP_HEADER_SIZE + SHAPE_HEADER_SIZE * max_frames as u64));
+
+        let start = try!(stream.stream_position());
+        try!(header.write_to(&mut stream));
+        let empty = SlpShapeHeader::new();
+        for _ in 0..max_frames {
+            try!(empty.write_to(&mut stream));
+        }
+
+        Ok(SlpStreamWriter {
+            stream,
+            start,
+            max_frames,
+            frame_count: 0,
+            end,
+            options: WriteOptions::default(),
+        })
+    }
+
+    /// Sets how later frames are encoded.
+    pub fn set_options(&mut self, options: WriteOptions) {
+        self.options = options;
+    }
+
+    /// Encodes `shape` and appends it to the file.
+    ///
+    /// Fails with `InvalidSlp` if the frame would end past the 4 GiB an SLP can address.
+    pub fn write_frame(&mut self, shape: &SlpLogicalShape) -> Result<()> {
+        if self.frame_count == self.max_frames {
+            return Err(ErrorKind::InvalidSlp(format!("only room for {} frames was reserved",
+                                                     self.max_frames))
+                .into());
+        }
+
+        let (header, data) = try!(encode_shape_at(shape, self.end, &self.options));
+        let end = try!(file_offset(self.end as u64 + data.len() as u64));
+        try!(self.stream.seek(SeekFrom::Start(self.start + self.end as u64)));
+        try!(self.stream.write_all(&data));
+
+        let header_offset = SLP_HEADER_SIZE + SHAPE_HEADER_SIZE * self.frame_count as u64;
+        try!(self.stream.seek(SeekFrom::Start(self.start + header_offset)));
+        try!(header.write_to(&mut self.stream));
+
+        self.end = end;
+        self.frame_count += 1;
+        Ok(())
+    }
+
+    /// Fills in the number of frames written and hands back the stream, positioned at the end
+    /// of the file.
+    pub fn finish(mut self) -> Result<W> {
+        // The frame count follows the 4 byte version
+        try!(self.stream.seek(SeekFrom::Start(self.start + 4)));
+        try!(self.stream.write_all(&u32_bytes(self.frame_count)));
+        try!(self.stream.seek(SeekFrom::Start(self.start + self.end as u64)));
+        Ok(self.stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SlpStreamWriter;
+    use error::{Error, ErrorKind};
+    use slp::{DrawCommand, SlpFile, SlpLogicalShape};
+    use std::io;
+    use test_util::single_shape_slp;
+
+    #[test]
+    fn test_slp_stream_writer() {
+        // One frame read from a file, so it's written from its raw bytes, and one built by hand
+        let data = single_shape_slp(3, &[(1, 0, &[0x08, 7, 8, 0x0F]), (0x8000, 0x8000, &[])]);
+        let read = SlpFile::read_from(&mut io::Cursor::new(data)).unwrap();
+        let mut built = SlpLogicalShape::new();
+        built.header.width = 2;
+        built.header.height = 1;
+        built.header.center_x = -3;
+        built.pixels = vec![5, 2];
+        built.commands = vec![vec![DrawCommand::Color { count: 1 },
+                                   DrawCommand::PlayerColor { relative: vec![2] }]];
+
+        let mut writer = SlpStreamWriter::begin(io::Cursor::new(Vec::new()), "test", 4).unwrap();
+        writer.write_frame(&read.shapes[0]).unwrap();
+        writer.write_frame(&built).unwrap();
+        let mut stream = writer.finish().unwrap();
+
+        stream.set_position(0);
+        let written = SlpFile::read_from(&mut stream).unwrap();
+        assert_eq!(2, written.header.shape_count);
+        assert_eq!("test", written.header.comment_str());
+        assert_eq!(read.shapes[0].pixels, written.shapes[0].pixels);
+        assert_eq!(read.shapes[0].commands, written.shapes[0].commands);
+        assert_eq!(built.pixels, written.shapes[1].pixels);
+        assert_eq!(built.commands, written.shapes[1].commands);
+        assert_eq!(-3, written.shapes[1].header.center_x);
+    }
+
+    #[test]
+    fn test_slp_stream_writer_max_frames() {
+        let mut shape = SlpLogicalShape::new();
+        shape.header.width = 1;
+        shape.header.height = 1;
+        shape.pixels = vec![0];
+        shape.commands = vec![vec![DrawCommand::Skip { count: 1 }]];
+
+        let mut writer = SlpStreamWriter::begin(io::Cursor::new(Vec::new()), "", 1).unwrap();
+        writer.write_frame(&shape).unwrap();
+        assert!(writer.write_frame(&shape).is_err());
+    }
+
+    #[test]
+    fn test_slp_stream_writer_begin_errors() {
+        // Comments fail the same way as when building a header
+        match SlpStreamWriter::begin(io::Cursor::new(Vec::new()), &"a".repeat(25), 1) {
+            Err(Error(ErrorKind::InvalidHeader(_), _)) => {}
+            other => panic!("expected InvalidHeader, got {:?}", other.map(|_| ())),
+        }
+        // Nothing is written when the headers alone would pass 4 GiB
+        match SlpStreamWriter::begin(io::Cursor::new(Vec::new()), "", u32::MAX / 32) {
+            Err(Error(ErrorKind::InvalidSlp(_), _)) => {}
+            other => panic!("expected InvalidSlp, got {:?}", other.map(|_| ())),
+        }
+    }
+}
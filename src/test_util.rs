@@ -0,0 +1,100 @@
+// Chariot: An open source reimplementation of Age of Empires (1997)
+// Copyright (c) 2016 Kevin Fuller
+// Copyright (c) 2017 Taryn Hill
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in all
+// copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+
+//! Helpers for building SLP fixtures in tests.
+
+use slp::{PixelKind, SlpLogicalShape, row_commands};
+
+/// A row of a fixture shape: its left offset, right padding and command bytes.
+pub type FixtureRow<'a> = (u16, u16, &'a [u8]);
+
+pub fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.push(value as u8);
+    data.push((value >> 8) as u8);
+}
+
+pub fn push_u32(data: &mut Vec<u8>, value: u32) {
+    push_u16(data, value as u16);
+    push_u16(data, (value >> 16) as u16);
+}
+
+/// Builds an SLP out of `(width, rows)` shapes.
+///
+/// Each shape's outline table, row offsets and commands are laid out one after the other,
+/// after the shape headers.
+pub fn build_slp(shapes: &[(u32, &[FixtureRow])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"2.0N");
+    push_u32(&mut data, shapes.len() as u32);
+    data.extend_from_slice(&[0u8; 24]);
+
+    let mut offset = 32 + 32 * shapes.len() as u32;
+    let mut shape_data = Vec::new();
+    for &(width, rows) in shapes {
+        let height = rows.len() as u32;
+        let outline_offset = offset;
+        let data_offsets = outline_offset + height * 4;
+        let mut command_offset = data_offsets + height * 4;
+
+        push_u32(&mut data, data_offsets);
+        push_u32(&mut data, outline_offset);
+        push_u32(&mut data, 0);
+        push_u32(&mut data, 0);
+        push_u32(&mut data, width);
+        push_u32(&mut data, height);
+        push_u32(&mut data, 0);
+        push_u32(&mut data, 0);
+
+        for &(left, right, _) in rows {
+            push_u16(&mut shape_data, left);
+            push_u16(&mut shape_data, right);
+        }
+        for &(_, _, commands) in rows {
+            push_u32(&mut shape_data, command_offset);
+            command_offset += commands.len() as u32;
+        }
+        for &(_, _, commands) in rows {
+            shape_data.extend_from_slice(commands);
+        }
+        offset = command_offset;
+    }
+    data.extend_from_slice(&shape_data);
+    data
+}
+
+/// Builds a single-shape SLP out of `(left, right_padding, commands)` rows.
+pub fn single_shape_slp(width: u32, rows: &[FixtureRow]) -> Vec<u8> {
+    build_slp(&[(width, rows)])
+}
+
+/// Gives `shape` one run per pixel described by `kinds`, in row-major order, taking the index of
+/// each from `pixels`, which must already be set.
+pub fn set_pixel_kinds(shape: &mut SlpLogicalShape, kinds: &[PixelKind]) {
+    let width = shape.header.width as usize;
+    let pixels: Vec<(PixelKind, u8)> =
+        kinds.iter().cloned().zip(shape.pixels.iter().cloned()).collect();
+    shape.commands = match width {
+        0 => vec![Vec::new(); shape.header.height as usize],
+        _ => pixels.chunks(width).map(|row| row_commands(row.iter().cloned())).collect(),
+    };
+}